@@ -0,0 +1,125 @@
+use std::sync::Arc;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use futures::executor::block_on;
+use futures::future::BoxFuture;
+use futures::io::AsyncReadExt;
+use surf::middleware::{Body, HttpClient, Middleware, Next, Request, Response};
+
+use surf_middleware_cache::{CACacheManager, Cache, CacheBuilder};
+
+const URL: &str = "https://example.com/bench";
+
+/// Origin handler the cache falls through to at the end of the chain.
+type Endpoint = dyn Fn(Request, NoClient) -> BoxFuture<'static, Result<Response, surf::Exception>>
+    + Send
+    + Sync;
+
+/// The endpoint answers every request itself, so nothing's ever sent.
+#[derive(Debug, Clone)]
+struct NoClient;
+
+impl HttpClient for NoClient {
+    type Error = std::io::Error;
+
+    fn send(&self, _req: Request) -> BoxFuture<'static, Result<Response, Self::Error>> {
+        unreachable!("the bench endpoint answers every request")
+    }
+}
+
+fn request() -> Request {
+    http::Request::get(URL).body(Body::empty()).unwrap()
+}
+
+fn response(cache_control: &str, body: Vec<u8>) -> Response {
+    http::Response::builder()
+        .status(200)
+        .header("Cache-Control", cache_control)
+        .header("ETag", "\"bench\"")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+/// An origin that serves `body` as fresh for an hour.
+fn fresh_origin(body: Vec<u8>) -> Box<Endpoint> {
+    Box::new(move |_req, _client| {
+        let res = response("max-age=3600", body.clone());
+        Box::pin(async move { Ok(res) })
+    })
+}
+
+/// An origin that serves `body` as needing revalidation every time, and
+/// answers the revalidations with a 304.
+fn revalidating_origin(body: Vec<u8>) -> Box<Endpoint> {
+    Box::new(move |req, _client| {
+        let res = if req.headers().contains_key("If-None-Match") {
+            http::Response::builder()
+                .status(304)
+                .header("ETag", "\"bench\"")
+                .body(Body::empty())
+                .unwrap()
+        } else {
+            response("no-cache", body.clone())
+        };
+        Box::pin(async move { Ok(res) })
+    })
+}
+
+fn cache(name: &str) -> Cache<CACacheManager> {
+    let path = std::env::temp_dir().join(format!("surf-cache-bench-{}", name));
+    CacheBuilder::new(CACacheManager::new(path.to_string_lossy().into_owned())).build()
+}
+
+/// Sends a request through `cache` and reads the whole response.
+async fn run(cache: &Cache<CACacheManager>, origin: &Endpoint) {
+    let chain: Vec<Arc<dyn Middleware<NoClient>>> = Vec::new();
+    let next = Next::new(&chain, origin);
+    let res = cache.run(request(), NoClient, next).await.unwrap();
+    let mut body = Vec::new();
+    res.into_body().read_to_end(&mut body).await.unwrap();
+}
+
+fn bench_body(c: &mut Criterion, label: &str, body: &[u8]) {
+    let fresh = fresh_origin(body.to_vec());
+
+    let cold = cache(&format!("{}-cold", label));
+    c.bench_function(&format!("{}: miss + store", label), |b| {
+        b.iter(|| {
+            block_on(async {
+                let url = surf::url::Url::parse(URL).unwrap();
+                cold.invalidate(http::Method::GET, url).await.unwrap();
+                run(&cold, &*fresh).await;
+            })
+        })
+    });
+
+    let warm = cache(&format!("{}-warm", label));
+    block_on(run(&warm, &*fresh));
+    c.bench_function(&format!("{}: hit", label), |b| {
+        b.iter(|| block_on(run(&warm, &*fresh)))
+    });
+
+    // What a 304 costs on our side: a conditional request upstream, then
+    // the stored entry read back, merged with the 304 and written out again.
+    let revalidating = revalidating_origin(body.to_vec());
+    let stale = cache(&format!("{}-stale", label));
+    block_on(run(&stale, &*revalidating));
+    c.bench_function(&format!("{}: revalidation round-trip", label), |b| {
+        b.iter(|| block_on(run(&stale, &*revalidating)))
+    });
+}
+
+fn small(c: &mut Criterion) {
+    bench_body(c, "1KB", &[b'x'; 1024]);
+}
+
+fn large(c: &mut Criterion) {
+    bench_body(c, "8MB", &vec![b'x'; 8 * 1024 * 1024]);
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = small, large
+}
+criterion_main!(benches);
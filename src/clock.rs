@@ -0,0 +1,47 @@
+//! `SystemTime::now()` panics on `wasm32-unknown-unknown`, so everything in
+//! this crate asks for the current time through here instead.
+
+use std::time::SystemTime;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn now() -> SystemTime {
+    #[cfg(test)]
+    {
+        if let Some(now) = mock::now() {
+            return now;
+        }
+    }
+    SystemTime::now()
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn now() -> SystemTime {
+    use std::time::{Duration, UNIX_EPOCH};
+    UNIX_EPOCH + Duration::from_millis(js_sys::Date::now() as u64)
+}
+
+/// A clock tests can stop and move by hand. It's per thread, so tests
+/// running side by side don't see each other's time.
+#[cfg(test)]
+pub(crate) mod mock {
+    use std::cell::Cell;
+    use std::time::{Duration, SystemTime};
+
+    thread_local! {
+        static NOW: Cell<Option<SystemTime>> = const { Cell::new(None) };
+    }
+
+    pub(crate) fn now() -> Option<SystemTime> {
+        NOW.with(Cell::get)
+    }
+
+    /// Stops the clock at the current time.
+    pub(crate) fn freeze() {
+        NOW.with(|now| now.set(Some(SystemTime::now())));
+    }
+
+    /// Moves the stopped clock forward.
+    pub(crate) fn advance(by: Duration) {
+        NOW.with(|now| now.set(Some(now.get().expect("The clock isn't frozen.") + by)));
+    }
+}
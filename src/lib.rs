@@ -1,260 +1,4596 @@
-use std::time::SystemTime;
+//! HTTP caching middleware for surf.
+//!
+//! The cache never spawns tasks of its own: all of its work, revalidation
+//! included, happens inside the future of the request being handled. Its
+//! only timer, used to back off between revalidation retries, comes from
+//! `futures-timer`. It therefore runs under async-std, tokio, or any other
+//! executor without needing a runtime feature.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 
 use async_trait::async_trait;
-use futures::future::BoxFuture;
+use futures::channel::mpsc;
+use futures::io::AsyncReadExt;
+use futures::stream::Stream;
+use futures_timer::Delay;
 use http::HeaderMap;
-use httpdate;
-use surf::middleware::{Body, HttpClient, Middleware, Next, Request, Response};
+use sha2::{Digest, Sha256};
+use surf::middleware::{Body, HttpClient, Next, Request, Response};
+
+mod clock;
+mod managers;
+mod normalize;
+mod policy;
+
+pub use managers::{ByteStore, ByteStoreManager, MemoryManager, TieredManager};
+#[cfg(not(target_arch = "wasm32"))]
+pub use managers::{CACacheManager, FsManager, HybridManager};
+#[cfg(feature = "test-util")]
+pub use managers::{MockCall, MockManager};
+pub use policy::{CacheOptions, CachePolicy};
 
 #[async_trait]
 pub trait CacheManager {
-    async fn get(&self, req: &Request) -> Result<Option<Response>, surf::Exception>;
-    async fn put(&self, req: &Request, res: Response) -> Result<Response, surf::Exception>;
+    async fn get(&self, req: &Request) -> Result<Option<CacheEntry>, surf::Exception>;
+    /// Stores `res` for `req`, handing back the response rebuilt from what
+    /// was actually stored, so it's identical to what later `get`s serve.
+    ///
+    /// If the returned future is dropped before it completes, later `get`s
+    /// must see either the previous entry or the new one, never a partial
+    /// or corrupt one.
+    async fn put(
+        &self,
+        req: &Request,
+        res: Response,
+        policy: CachePolicy,
+    ) -> Result<Response, surf::Exception>;
     async fn delete(&self, req: &Request) -> Result<(), surf::Exception>;
+
+    /// Looks up only the stored policy for a request, without materializing
+    /// the response body. The default implementation calls `get` and throws
+    /// the body away; managers that can read the policy on its own should
+    /// override this.
+    async fn get_metadata(&self, req: &Request) -> Result<Option<CachePolicy>, surf::Exception> {
+        Ok(self.get(req).await?.map(|entry| entry.policy))
+    }
+
+    /// Whether anything is stored for a request, without reading it.
+    async fn contains(&self, req: &Request) -> Result<bool, surf::Exception> {
+        Ok(self.get_metadata(req).await?.is_some())
+    }
+
+    /// Lists every stored entry along with when it was stored and when it
+    /// stops being fresh, e.g. for pruning or eviction. Managers that can't
+    /// enumerate their contents return an error.
+    async fn list(&self) -> Result<Vec<EntryInfo>, surf::Exception> {
+        Err("This cache manager doesn't support listing entries".into())
+    }
+
+    /// Removes every entry that's past both its freshness lifetime and any
+    /// `stale-while-revalidate`/`stale-if-error` grace period, returning how
+    /// many were removed.
+    async fn purge_expired(&self) -> Result<usize, surf::Exception> {
+        Err("This cache manager doesn't support purging entries".into())
+    }
+
+    /// Removes every entry whose key matches `predicate`, e.g. everything
+    /// under a path prefix after a deploy, returning how many were removed.
+    async fn delete_matching(
+        &self,
+        _predicate: &(dyn for<'k> Fn(&'k str) -> bool + Send + Sync),
+    ) -> Result<usize, surf::Exception> {
+        Err("This cache manager doesn't support deleting by key".into())
+    }
+
+    /// Every stored entry along with its key, e.g. for dumping the cache's
+    /// contents while debugging. Every body is read into memory at once, so
+    /// this gets expensive for large caches; managers may offer a streaming
+    /// alternative. Managers that can't enumerate their contents return an
+    /// error.
+    async fn entries(&self) -> Result<Vec<(String, CacheEntry)>, surf::Exception> {
+        Err("This cache manager doesn't support listing entries".into())
+    }
+
+    /// Removes every entry written by an older version of this crate, whose
+    /// layout may no longer be readable, returning how many were removed.
+    /// Such entries are already treated as misses on `get`; this just
+    /// reclaims their space up front, e.g. after an upgrade.
+    async fn migrate(&self) -> Result<usize, surf::Exception> {
+        Err("This cache manager doesn't support migrating entries".into())
+    }
+
+    /// Measurements taken when the entry for a request was stored, e.g. to
+    /// find slow endpoints worth prefetching.
+    async fn stats(&self, _req: &Request) -> Result<Option<EntryStats>, surf::Exception> {
+        Err("This cache manager doesn't record entry stats".into())
+    }
+
+    /// Looks up several requests at once, returning their entries in the
+    /// same order. The default implementation calls `get` for each in turn;
+    /// managers that can batch or parallelize reads should override this.
+    async fn get_many(&self, reqs: &[Request]) -> Result<Vec<Option<CacheEntry>>, surf::Exception> {
+        let mut entries = Vec::with_capacity(reqs.len());
+        for req in reqs {
+            entries.push(self.get(req).await?);
+        }
+        Ok(entries)
+    }
+
+    /// Stores several responses at once, e.g. when warming the cache,
+    /// handing back the stored responses in the same order. The default
+    /// implementation calls `put` for each in turn; managers that can batch
+    /// or parallelize writes should override this.
+    async fn put_many(
+        &self,
+        entries: Vec<(Request, Response, CachePolicy)>,
+    ) -> Result<Vec<Response>, surf::Exception> {
+        let mut responses = Vec::with_capacity(entries.len());
+        for (req, res, policy) in entries {
+            responses.push(self.put(&req, res, policy).await?);
+        }
+        Ok(responses)
+    }
+}
+
+/// Hex-encoded SHA-256 of a request's body, attached to the request's
+/// extensions when its method is configured as cacheable but isn't `GET` or
+/// `HEAD`. Managers include it in their keys so requests with different
+/// bodies don't share an entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BodyHash(pub String);
+
+/// Normalized `Accept-Encoding` of a request, attached to the request's
+/// extensions when storing or looking up one encoding of a response that
+/// varies on it. Managers include it in their keys, so each encoding gets an
+/// entry of its own, alongside the one for the request without it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodingVariant(pub String);
+
+/// Cache partition a request belongs to, attached to the request's
+/// extensions when [`CacheBuilder::partition`] is configured. Managers
+/// prefix their keys with it, so partitions never share entries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Partition(pub String);
+
+/// A stored response, as handed back by [`CacheManager::get`].
+#[derive(Debug)]
+pub struct CacheEntry {
+    pub response: Response,
+    pub policy: CachePolicy,
+    /// When the response was stored.
+    pub stored_at: SystemTime,
+    /// Namespace of the manager the entry was found in, if it has one.
+    pub namespace: Option<String>,
+}
+
+/// Bookkeeping details about a single stored entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryInfo {
+    /// Key the entry is stored under.
+    pub key: String,
+    /// When the entry was stored.
+    pub stored_at: SystemTime,
+    /// When the entry stops being fresh.
+    pub fresh_until: SystemTime,
+}
+
+/// Measurements recorded alongside a stored entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EntryStats {
+    /// How long the origin took to produce the response, in milliseconds,
+    /// if it was fetched through the cache.
+    pub origin_latency_ms: Option<u64>,
+    /// Length of the response body, before any storage-level compression.
+    pub body_len: Option<u64>,
+}
+
+/// How long the origin took to respond, attached to the extensions of
+/// responses fetched through the cache so managers can record it.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct OriginLatency(pub(crate) Duration);
+
+/// Status the origin answered a revalidation with, attached to the
+/// extensions of responses it revalidated for their `Cache-Status` header.
+#[derive(Debug, Clone, Copy)]
+struct ForwardStatus(http::StatusCode);
+
+impl OriginLatency {
+    pub(crate) fn millis(extensions: &http::Extensions) -> Option<u64> {
+        extensions
+            .get::<OriginLatency>()
+            .map(|OriginLatency(latency)| latency.as_millis() as u64)
+    }
+}
+
+/// A [`CacheManager`] whose concrete type is picked at runtime.
+pub type BoxedCacheManager = Box<dyn CacheManager + Send + Sync>;
+
+#[async_trait]
+impl<T: CacheManager + Send + Sync + ?Sized> CacheManager for Box<T> {
+    async fn get(&self, req: &Request) -> Result<Option<CacheEntry>, surf::Exception> {
+        (**self).get(req).await
+    }
+
+    async fn put(
+        &self,
+        req: &Request,
+        res: Response,
+        policy: CachePolicy,
+    ) -> Result<Response, surf::Exception> {
+        (**self).put(req, res, policy).await
+    }
+
+    async fn delete(&self, req: &Request) -> Result<(), surf::Exception> {
+        (**self).delete(req).await
+    }
+
+    async fn get_metadata(&self, req: &Request) -> Result<Option<CachePolicy>, surf::Exception> {
+        (**self).get_metadata(req).await
+    }
+
+    async fn contains(&self, req: &Request) -> Result<bool, surf::Exception> {
+        (**self).contains(req).await
+    }
+
+    async fn list(&self) -> Result<Vec<EntryInfo>, surf::Exception> {
+        (**self).list().await
+    }
+
+    async fn purge_expired(&self) -> Result<usize, surf::Exception> {
+        (**self).purge_expired().await
+    }
+
+    async fn delete_matching(
+        &self,
+        predicate: &(dyn for<'k> Fn(&'k str) -> bool + Send + Sync),
+    ) -> Result<usize, surf::Exception> {
+        (**self).delete_matching(predicate).await
+    }
+
+    async fn entries(&self) -> Result<Vec<(String, CacheEntry)>, surf::Exception> {
+        (**self).entries().await
+    }
+
+    async fn migrate(&self) -> Result<usize, surf::Exception> {
+        (**self).migrate().await
+    }
+
+    async fn stats(&self, req: &Request) -> Result<Option<EntryStats>, surf::Exception> {
+        (**self).stats(req).await
+    }
+
+    async fn get_many(&self, reqs: &[Request]) -> Result<Vec<Option<CacheEntry>>, surf::Exception> {
+        (**self).get_many(reqs).await
+    }
+
+    async fn put_many(
+        &self,
+        entries: Vec<(Request, Response, CachePolicy)>,
+    ) -> Result<Vec<Response>, surf::Exception> {
+        (**self).put_many(entries).await
+    }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum CacheMode {
     Default,
     NoStore,
     Reload,
     NoCache,
     ForceCache,
+    /// Never goes to the network, e.g. for apps that must keep working
+    /// offline once the cache is warm. Stored responses are served however
+    /// stale they are, with a `112 Disconnected operation` warning if they
+    /// are, and misses get a `504 Gateway Timeout` saying the request
+    /// wasn't in the cache.
     OnlyIfCached,
 }
 
+/// How the cache ended up answering a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// Served a fresh response from the cache, with however long it has
+    /// left to stay fresh.
+    Hit(Duration),
+    /// Served a stale response from the cache, because revalidating it
+    /// failed or the mode said not to try.
+    Stale,
+    /// A stored response was revalidated with the origin.
+    Revalidated,
+    /// Nothing usable was stored, so the request went to the origin.
+    Miss,
+    /// The request wasn't eligible for caching and went to the origin.
+    Bypass,
+    /// Nothing was stored, and the request wasn't allowed to go to the
+    /// origin.
+    NotCached,
+}
+
+impl Outcome {
+    /// Short name for the outcome, for logging.
+    fn decision(self) -> &'static str {
+        match self {
+            Outcome::Hit(_) => "hit",
+            Outcome::Stale => "stale",
+            Outcome::Revalidated => "revalidated",
+            Outcome::Miss => "miss",
+            Outcome::Bypass => "bypass",
+            Outcome::NotCached => "not-cached",
+        }
+    }
+
+    /// Renders the outcome of the request answered with `res` as a
+    /// `Cache-Status` header value.
+    /// (https://tools.ietf.org/html/rfc9211)
+    fn cache_status(self, key: &str, res: &Response) -> String {
+        let status = res.status();
+        let params = match self {
+            Outcome::Hit(ttl) => format!("hit; ttl={}", ttl.as_secs()),
+            // The ttl of a stale response is how long ago it went stale, as
+            // a negative number.
+            Outcome::Stale => {
+                let ttl = res.extensions().get::<CachePolicy>().map_or(0, |policy| {
+                    policy.max_age().as_secs() as i64 - policy.age(clock::now()).as_secs() as i64
+                });
+                format!("hit; ttl={}", ttl)
+            }
+            // `fwd-status` is what the origin answered the revalidation
+            // with, e.g. a 304, rather than what's handed back.
+            Outcome::Revalidated => format!(
+                "fwd=stale; fwd-status={}",
+                res.extensions()
+                    .get::<ForwardStatus>()
+                    .map_or(status, |ForwardStatus(status)| *status)
+                    .as_u16()
+            ),
+            Outcome::Miss => format!("fwd=miss; fwd-status={}", status.as_u16()),
+            Outcome::Bypass => format!("fwd=bypass; fwd-status={}", status.as_u16()),
+            Outcome::NotCached => "detail=not-cached".to_string(),
+        };
+        format!(
+            "surf-cache; {}; key=\"{}\"",
+            params,
+            key.replace('\\', "\\\\").replace('"', "\\\"")
+        )
+    }
+}
+
+/// Attached to the extensions of every response the cache hands back, so
+/// callers can tell where it came from without parsing `Warning` headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CacheStatus {
+    /// Served from the cache while still fresh.
+    Fresh,
+    /// Served from the cache past its freshness lifetime.
+    Stale,
+    /// Served from the cache after the origin confirmed it was current.
+    Revalidated,
+    /// Fetched from the origin.
+    Miss,
+}
+
+impl From<Outcome> for CacheStatus {
+    fn from(outcome: Outcome) -> Self {
+        match outcome {
+            Outcome::Hit(_) => CacheStatus::Fresh,
+            Outcome::Stale => CacheStatus::Stale,
+            Outcome::Revalidated => CacheStatus::Revalidated,
+            Outcome::Miss | Outcome::Bypass | Outcome::NotCached => CacheStatus::Miss,
+        }
+    }
+}
+
+/// Reported on [`Cache::event_stream`] for every request the cache handles.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheEvent {
+    /// Cache key of the request.
+    pub key: String,
+    /// How the request was answered.
+    pub outcome: Outcome,
+    /// Status of the response handed back.
+    pub status: http::StatusCode,
+}
+
+/// How many events each [`Cache::event_stream`] subscriber can have queued
+/// before new ones start getting dropped.
+const EVENT_BUFFER: usize = 64;
+
 /// Caches requests according to http spec
 #[derive(Debug)]
 pub struct Cache<T: CacheManager> {
     mode: CacheMode,
     cache_manager: T,
+    options: CacheOptions,
+    normalize_urls: bool,
+    inject_warnings: bool,
+    fail_open: bool,
+    ttl_header: bool,
+    cacheable_methods: Vec<http::Method>,
+    cache_status_header: bool,
+    bypass: Option<Hook<BypassFn>>,
+    should_cache: Option<Hook<ShouldCacheFn>>,
+    subscribers: Mutex<Vec<mpsc::Sender<CacheEvent>>>,
+    max_served_age: Option<Duration>,
+    revalidation_retries: u32,
+    revalidation_backoff: Duration,
+    warn_agent: Option<String>,
+    range_revalidation: bool,
+    strip_headers: Vec<http::header::HeaderName>,
+    partition: Option<Hook<PartitionFn>>,
+    status_ttls: HashMap<http::StatusCode, Duration>,
+    ignore_vary: bool,
+    on_serve: Option<Hook<OnServeFn>>,
+    reload_fallback_to_cache: bool,
 }
 
-impl<T: CacheManager> Cache<T> {
-    pub async fn run<'a, C: HttpClient>(
-        &self,
-        req: Request,
-        client: C,
-        next: Next<'a, C>,
-    ) -> Result<Response, surf::Exception> {
-        let is_cacheable = (req.method() == http::Method::GET
-            || req.method() == http::Method::HEAD)
-            && self.mode != CacheMode::NoStore
-            && self.mode != CacheMode::Reload;
+/// Wherever the cache sends the requests it can't answer from its store.
+/// Normally that's the rest of a surf middleware chain, but anything that
+/// turns a request into a response will do, e.g. a canned origin in tests.
+#[async_trait]
+pub(crate) trait OriginFetch {
+    async fn fetch(&self, req: Request) -> Result<Response, surf::Exception>;
+}
 
-        if !is_cacheable {
-            return Ok(self.remote_fetch(req, client, next).await?);
-        }
-
-        if let Some(mut res) = self.cache_manager.get(&req).await? {
-            if let Some(warning_code) = get_warning_code(&res) {
-                // https://tools.ietf.org/html/rfc7234#section-4.3.4
-                //
-                // If a stored response is selected for update, the cache MUST:
-                //
-                // * delete any Warning header fields in the stored response with
-                //   warn-code 1xx (see Section 5.5);
-                //
-                // * retain any Warning header fields in the stored response with
-                //   warn-code 2xx;
-                //
-                if warning_code >= 100 && warning_code < 200 {
-                    res.headers_mut().remove("Warning");
-                }
-            }
+/// The rest of a surf middleware chain, as an [`OriginFetch`].
+struct NextFetch<'a, C: HttpClient> {
+    client: C,
+    next: Next<'a, C>,
+}
 
-            if self.mode == CacheMode::Default && !is_stale(&req, &res) {
-                Ok(res)
-            } else if self.mode == CacheMode::Default {
-                Ok(self.conditional_fetch(req, res, client, next).await?)
-            } else if self.mode == CacheMode::ForceCache || self.mode == CacheMode::OnlyIfCached {
-                //   112 Disconnected operation
-                // SHOULD be included if the cache is intentionally disconnected from
-                // the rest of the network for a period of time.
-                // (https://tools.ietf.org/html/rfc2616#section-14.46)
-                add_warning(&req.uri(), res.headers_mut(), 112, "Disconnected operation");
-                Ok(res)
-            } else {
-                Ok(self.remote_fetch(req, client, next).await?)
-            }
-        } else if self.mode == CacheMode::OnlyIfCached {
-            // ENOTCACHED
-            unimplemented!()
-        } else {
-            Ok(self.remote_fetch(req, client, next).await?)
+#[async_trait]
+impl<'a, C: HttpClient> OriginFetch for NextFetch<'a, C> {
+    async fn fetch(&self, req: Request) -> Result<Response, surf::Exception> {
+        self.next.run(req, self.client.clone()).await
+    }
+}
+
+/// A user-supplied callback, wrapped up so it can sit in a struct that
+/// derives `Debug`.
+#[derive(Clone)]
+struct Hook<F: ?Sized>(Arc<F>);
+
+impl<F: ?Sized> fmt::Debug for Hook<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Hook")
+    }
+}
+
+// The callbacks each of the builder's hooks is given as.
+type BypassFn = dyn Fn(&Request) -> bool + Send + Sync;
+type ShouldCacheFn = dyn Fn(&Request, &Response) -> bool + Send + Sync;
+type PartitionFn = dyn Fn(&Request) -> Option<String> + Send + Sync;
+type OnServeFn = dyn Fn(&mut Response) + Send + Sync;
+
+/// Builds a [`Cache`] with non-default settings.
+#[derive(Debug)]
+pub struct CacheBuilder<T: CacheManager> {
+    cache: Cache<T>,
+}
+
+impl<T: CacheManager> CacheBuilder<T> {
+    pub fn new(cache_manager: T) -> Self {
+        CacheBuilder {
+            cache: Cache {
+                mode: CacheMode::Default,
+                cache_manager,
+                options: CacheOptions::default(),
+                normalize_urls: false,
+                inject_warnings: true,
+                fail_open: true,
+                ttl_header: false,
+                cacheable_methods: vec![http::Method::GET, http::Method::HEAD],
+                cache_status_header: false,
+                bypass: None,
+                should_cache: None,
+                subscribers: Mutex::new(Vec::new()),
+                max_served_age: None,
+                revalidation_retries: 0,
+                revalidation_backoff: Duration::from_millis(100),
+                warn_agent: None,
+                range_revalidation: false,
+                strip_headers: vec![http::header::SET_COOKIE],
+                partition: None,
+                status_ttls: HashMap::new(),
+                ignore_vary: false,
+                on_serve: None,
+                reload_fallback_to_cache: false,
+            },
         }
     }
 
-    async fn conditional_fetch<'a, C: HttpClient>(
-        &self,
-        mut req: Request,
-        mut cached_res: Response,
-        client: C,
-        next: Next<'a, C>,
-    ) -> Result<Response, surf::Exception> {
-        set_revalidation_headers(&mut req);
-        let copied_req = clone_req(&req);
-        match self.remote_fetch(req, client, next).await {
-            Ok(cond_res) => {
-                if cond_res.status().is_server_error() && must_revalidate(&cached_res) {
-                    //   111 Revalidation failed
-                    //   MUST be included if a cache returns a stale response
-                    //   because an attempt to revalidate the response failed,
-                    //   due to an inability to reach the server.
-                    // (https://tools.ietf.org/html/rfc2616#section-14.46)
-                    add_warning(
-                        &copied_req.uri(),
-                        cached_res.headers_mut(),
-                        111,
-                        "Revalidation failed",
-                    );
-                    Ok(cached_res)
-                } else if cond_res.status() == http::StatusCode::NOT_MODIFIED {
-                    let mut res = http::Response::builder();
-                    res.status(cond_res.status());
-                    let headers = res.headers_mut().expect("Couldn't get headers.");
-                    for (key, value) in cond_res.headers().into_iter() {
-                        headers.append(key, value.clone());
-                    }
-                    // TODO - set headers to revalidated response headers? Needs http-cache-semantics.
-                    let res = res.body(cached_res.into_body()).unwrap();
-                    let res = self.cache_manager.put(&copied_req, res).await?;
-                    Ok(res)
-                } else {
-                    Ok(cached_res)
-                }
-            }
-            Err(e) => {
-                if must_revalidate(&cached_res) {
-                    Err(e)
-                } else {
-                    let mut headers = cached_res.headers_mut();
-                    //   111 Revalidation failed
-                    //   MUST be included if a cache returns a stale response
-                    //   because an attempt to revalidate the response failed,
-                    //   due to an inability to reach the server.
-                    // (https://tools.ietf.org/html/rfc2616#section-14.46)
-                    add_warning(&copied_req.uri(), &mut headers, 111, "Revalidation failed");
-                    //   199 Miscellaneous warning
-                    //   The warning text MAY include arbitrary information to
-                    //   be presented to a human user, or logged. A system
-                    //   receiving this warning MUST NOT take any automated
-                    //   action, besides presenting the warning to the user.
-                    // (https://tools.ietf.org/html/rfc2616#section-14.46)
-                    add_warning(
-                        &copied_req.uri(),
-                        &mut headers,
-                        199,
-                        format!("Miscellaneous Warning {}", e).as_str(),
-                    );
+    pub fn mode(mut self, mode: CacheMode) -> Self {
+        self.cache.mode = mode;
+        self
+    }
 
-                    Ok(cached_res)
-                }
-            }
+    /// Whether this cache is shared between users, like a proxy, rather than
+    /// private to a single user. Defaults to `false`.
+    pub fn shared(mut self, shared: bool) -> Self {
+        self.cache.options.shared = shared;
+        self
+    }
+
+    /// Freshness lifetime to use for responses that come back without any
+    /// caching headers of their own. Responses marked `no-store` or
+    /// `no-cache` still aren't cached. Defaults to `None`.
+    pub fn default_ttl(mut self, ttl: Duration) -> Self {
+        self.cache.options.default_ttl = Some(ttl);
+        self
+    }
+
+    /// Fraction of the time since its `Last-Modified` date that a response
+    /// without an explicit lifetime stays fresh for. Defaults to `0.1`.
+    pub fn cache_heuristic(mut self, cache_heuristic: f32) -> Self {
+        self.cache.options.cache_heuristic = cache_heuristic;
+        self
+    }
+
+    /// Whether to ignore `no-cache`, `no-store` and `must-revalidate` on
+    /// responses that also carry IE's `pre-check` and `post-check`
+    /// directives. Defaults to `false`.
+    pub fn ignore_cargo_cult(mut self, ignore_cargo_cult: bool) -> Self {
+        self.cache.options.ignore_cargo_cult = ignore_cargo_cult;
+        self
+    }
+
+    /// Whether this cache is a surrogate, like a CDN edge, that honors
+    /// `Surrogate-Control` over `Cache-Control`. Defaults to `false`.
+    pub fn surrogate(mut self, surrogate: bool) -> Self {
+        self.cache.options.surrogate = surrogate;
+        self
+    }
+
+    /// Whether to normalize request URLs before using them as cache keys, so
+    /// that equivalent URLs share an entry. This lowercases the scheme and
+    /// host, drops default ports and trailing dots on hosts, uppercases
+    /// percent-encodings, and sorts query parameters by name, then value,
+    /// treating `a` and `a=` as the same. Requests sent to the origin are
+    /// left untouched. Defaults to `false`.
+    pub fn normalize_urls(mut self, normalize_urls: bool) -> Self {
+        self.cache.normalize_urls = normalize_urls;
+        self
+    }
+
+    /// Whether to add `Warning` headers to responses served from the cache
+    /// when they're disconnected, stale, or failed revalidation. Defaults to
+    /// `true`.
+    pub fn inject_warnings(mut self, inject_warnings: bool) -> Self {
+        self.cache.inject_warnings = inject_warnings;
+        self
+    }
+
+    /// Whether errors from the cache manager while reading or storing
+    /// entries should be logged and otherwise ignored, letting the request
+    /// go through to the origin as if nothing was cached. Defaults to
+    /// `true`.
+    pub fn fail_open(mut self, fail_open: bool) -> Self {
+        self.cache.fail_open = fail_open;
+        self
+    }
+
+    /// Whether to add an `X-Cache-TTL` header to responses served from the
+    /// cache, with the number of seconds they'll stay fresh for. Meant for
+    /// debugging. Defaults to `false`.
+    pub fn ttl_header(mut self, ttl_header: bool) -> Self {
+        self.cache.ttl_header = ttl_header;
+        self
+    }
+
+    /// Request methods whose responses may be cached. Defaults to `GET` and
+    /// `HEAD`.
+    ///
+    /// Opting into anything else, like `POST`, is risky: those requests
+    /// usually have side effects, and answering one from the cache means the
+    /// origin never sees it. Only do this for endpoints you know are safe to
+    /// replay and that send explicit freshness information. Requests with a
+    /// body get a [`BodyHash`] of it mixed into their key. Responses to
+    /// methods not listed here invalidate the stored entry for their URL.
+    pub fn cacheable_methods(mut self, methods: impl IntoIterator<Item = http::Method>) -> Self {
+        self.cache.cacheable_methods = methods.into_iter().collect();
+        self
+    }
+
+    /// Whether to add a `Cache-Status` header (RFC 9211) to every response,
+    /// describing whether it was a hit, and if not, why the request was
+    /// forwarded to the origin. Defaults to `false`.
+    pub fn cache_status_header(mut self, cache_status_header: bool) -> Self {
+        self.cache.cache_status_header = cache_status_header;
+        self
+    }
+
+    /// Skips the cache entirely, as if the mode were `NoStore`, for any
+    /// request `bypass` returns `true` for, e.g. everything under `/auth/`.
+    /// Matching requests are never read from or written to the store.
+    pub fn bypass(mut self, bypass: impl Fn(&Request) -> bool + Send + Sync + 'static) -> Self {
+        self.cache.bypass = Some(Hook(Arc::new(bypass)));
+        self
+    }
+
+    /// Only stores responses `should_cache` returns `true` for, on top of
+    /// the usual HTTP caching rules. It can only veto storing a response the
+    /// cache would otherwise keep, never force one to be kept.
+    ///
+    /// The hook only sees the status and headers: it runs before any of the
+    /// body is read, so vetoed responses are handed back unbuffered.
+    pub fn should_cache(
+        mut self,
+        should_cache: impl Fn(&Request, &Response) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.cache.should_cache = Some(Hook(Arc::new(should_cache)));
+        self
+    }
+
+    /// Calls `on_serve` on every response served from the store, fresh,
+    /// stale or revalidated, right before it's handed back, e.g. to add a
+    /// header saying so or drop internal ones. Responses fetched from the
+    /// origin without being served from the store never see it, and
+    /// nothing it changes is stored.
+    pub fn on_serve(mut self, on_serve: impl Fn(&mut Response) + Send + Sync + 'static) -> Self {
+        self.cache.on_serve = Some(Hook(Arc::new(on_serve)));
+        self
+    }
+
+    /// Revalidates any stored response older than `max_served_age` before
+    /// serving it, even if it would otherwise still be fresh. Defaults to
+    /// `None`.
+    pub fn max_served_age(mut self, max_served_age: Duration) -> Self {
+        self.cache.max_served_age = Some(max_served_age);
+        self
+    }
+
+    /// Retries a revalidation that fails with a network error up to
+    /// `retries` times before falling back to the stored response, or to the
+    /// error if it has to be revalidated. The wait before each retry starts
+    /// at `backoff` and doubles every time, up to `backoff` times 2^16.
+    /// Defaults to no retries.
+    pub fn revalidation_retries(mut self, retries: u32, backoff: Duration) -> Self {
+        self.cache.revalidation_retries = retries;
+        self.cache.revalidation_backoff = backoff;
+        self
+    }
+
+    /// Name the cache identifies itself by in the `Warning` headers it adds.
+    /// Defaults to the host of the request URL, or `-` if it has none.
+    /// Characters that can't appear in a header, and spaces, are replaced
+    /// with `-`.
+    pub fn warn_agent(mut self, warn_agent: impl Into<String>) -> Self {
+        let warn_agent: String = warn_agent
+            .into()
+            .chars()
+            .map(|c| if c.is_ascii_graphic() { c } else { '-' })
+            .collect();
+        self.cache.warn_agent = Some(if warn_agent.is_empty() {
+            "-".to_string()
+        } else {
+            warn_agent
+        });
+        self
+    }
+
+    /// Revalidates stored responses whose origin accepts byte ranges by
+    /// asking, with `If-Range`, only for the bytes past the end of the stored
+    /// copy, and appending them to it. Meant for resources that only ever
+    /// grow, like logs; anything else that changed comes back in full and
+    /// replaces the stored copy as usual. Defaults to `false`.
+    pub fn range_revalidation(mut self, range_revalidation: bool) -> Self {
+        self.cache.range_revalidation = range_revalidation;
+        self
+    }
+
+    /// Response headers to leave out of stored entries, so they're never
+    /// replayed to anyone but the caller the response was fetched for.
+    /// Defaults to just `Set-Cookie`.
+    pub fn strip_headers(
+        mut self,
+        headers: impl IntoIterator<Item = http::header::HeaderName>,
+    ) -> Self {
+        self.cache.strip_headers = headers.into_iter().collect();
+        self
+    }
+
+    /// Partitions the cache by whatever `partition` returns for each
+    /// request, e.g. the top-level site it was made on behalf of, so the same
+    /// URL cached under different partitions is stored separately and
+    /// never leaks between them. Requests it returns `None` for share the
+    /// unpartitioned cache.
+    pub fn partition(
+        mut self,
+        partition: impl Fn(&Request) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.cache.partition = Some(Hook(Arc::new(partition)));
+        self
+    }
+
+    /// Stores responses with `status` and serves them from the cache for
+    /// `ttl`, whatever their own headers say about freshness. Meant for
+    /// briefly caching errors, e.g. `503`s, to take load off a struggling
+    /// origin. `no-store` is still honored.
+    pub fn status_ttl(mut self, status: http::StatusCode, ttl: Duration) -> Self {
+        self.cache.status_ttls.insert(status, ttl);
+        self
+    }
+
+    /// Ignores `Vary` entirely, so entries are keyed on just the method and
+    /// URL, for origins that send `Vary` headers they don't actually honor.
+    /// Defaults to `false`, which is what the spec requires.
+    pub fn ignore_vary(mut self, ignore_vary: bool) -> Self {
+        self.cache.ignore_vary = ignore_vary;
+        self
+    }
+
+    /// Whether `Reload` requests fall back to the stored response, with a
+    /// `111 Revalidation failed` warning, when the origin can't be reached
+    /// or answers with a server error. Stale responses marked
+    /// `must-revalidate` are still never served. Defaults to `false`, where
+    /// reloads fail whenever the origin does.
+    pub fn reload_fallback_to_cache(mut self, reload_fallback_to_cache: bool) -> Self {
+        self.cache.reload_fallback_to_cache = reload_fallback_to_cache;
+        self
+    }
+
+    pub fn build(self) -> Cache<T> {
+        self.cache
+    }
+}
+
+impl<T: CacheManager> Cache<T> {
+    pub fn new(mode: CacheMode, cache_manager: T) -> Self {
+        CacheBuilder::new(cache_manager).mode(mode).build()
+    }
+
+    pub fn builder(cache_manager: T) -> CacheBuilder<T> {
+        CacheBuilder::new(cache_manager)
+    }
+
+    /// This cache, with its mode switched to `mode`.
+    pub fn with_mode(mut self, mode: CacheMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// This cache, with all the same settings, on top of a different
+    /// manager. Event subscribers carry over too.
+    pub fn with_manager<U: CacheManager>(self, cache_manager: U) -> Cache<U> {
+        Cache {
+            mode: self.mode,
+            cache_manager,
+            options: self.options,
+            normalize_urls: self.normalize_urls,
+            inject_warnings: self.inject_warnings,
+            fail_open: self.fail_open,
+            ttl_header: self.ttl_header,
+            cacheable_methods: self.cacheable_methods,
+            cache_status_header: self.cache_status_header,
+            bypass: self.bypass,
+            should_cache: self.should_cache,
+            subscribers: self.subscribers,
+            max_served_age: self.max_served_age,
+            revalidation_retries: self.revalidation_retries,
+            revalidation_backoff: self.revalidation_backoff,
+            warn_agent: self.warn_agent,
+            range_revalidation: self.range_revalidation,
+            strip_headers: self.strip_headers,
+            partition: self.partition,
+            status_ttls: self.status_ttls,
+            ignore_vary: self.ignore_vary,
+            on_serve: self.on_serve,
+            reload_fallback_to_cache: self.reload_fallback_to_cache,
         }
     }
 
-    async fn remote_fetch<'a, C: HttpClient>(
+    /// Fetches `req` from the network and stores the response, if it can be
+    /// stored, without handing it back. Useful for warming the cache ahead of
+    /// time. This goes straight to `client`, skipping any other middleware.
+    pub async fn prefetch<C: HttpClient>(
         &self,
         req: Request,
         client: C,
-        next: Next<'a, C>,
-    ) -> Result<Response, surf::Exception> {
-        let copied_req = clone_req(&req);
-        let res = next.run(req, client).await?;
-        let is_method_get_head =
-            copied_req.method() == http::Method::GET || copied_req.method() == http::Method::HEAD;
-        let is_cacheable = self.mode != CacheMode::NoStore
-            && is_method_get_head
-            && res.status() == http::StatusCode::OK;
-        // TODO
-        // && policy.is_storable(&req_copy, &res);
-        if is_cacheable {
-            Ok(self.cache_manager.put(&copied_req, res).await?)
-        } else if !is_method_get_head {
-            self.cache_manager.delete(&copied_req).await?;
-            Ok(res)
-        } else {
-            Ok(res)
-        }
+    ) -> Result<(), surf::Exception>
+    where
+        T: Sync,
+    {
+        let req = self.hash_body(req).await?;
+        let copied_req = self.key_req(&req);
+        let started = clock::now();
+        let mut res = match client.send(req).await {
+            Ok(res) => res,
+            Err(e) => return Err(e.into()),
+        };
+        let latency = clock::now().duration_since(started).unwrap_or_default();
+        res.extensions_mut().insert(OriginLatency(latency));
+        self.handle_response(copied_req, res).await?;
+        Ok(())
     }
-}
 
-fn must_revalidate(res: &Response) -> bool {
-    if let Some(val) = res
-        .headers()
-        .get("Cache-Control")
-        .and_then(|h| h.to_str().ok())
+    /// Drops whatever is stored for `method` requests to `url`, e.g. after
+    /// making a change the cached response wouldn't reflect.
+    pub async fn invalidate(
+        &self,
+        method: http::Method,
+        url: surf::url::Url,
+    ) -> Result<(), surf::Exception>
+    where
+        T: Sync,
     {
-        val.to_lowercase().contains("must-revalidate")
-    } else {
-        false
+        let req = http::Request::builder()
+            .method(method)
+            .uri(url.as_str())
+            .body(Body::empty())?;
+        self.delete_entry(&self.key_req(&req)).await
     }
-}
 
-fn set_revalidation_headers(mut req: &Request) {
-    // TODO - need http-cache-semantics to do this.
-    unimplemented!()
-}
+    /// How much longer the response stored for `method` requests to `url`
+    /// stays fresh, e.g. for a scheduler deciding what to refresh ahead of
+    /// time. Stale responses report zero, and `None` means nothing is
+    /// stored.
+    pub async fn time_to_live(
+        &self,
+        method: http::Method,
+        url: surf::url::Url,
+    ) -> Result<Option<Duration>, surf::Exception>
+    where
+        T: Sync,
+    {
+        let req = http::Request::builder()
+            .method(method)
+            .uri(url.as_str())
+            .body(Body::empty())?;
+        Ok(self
+            .cache_manager
+            .get_metadata(&self.key_req(&req))
+            .await?
+            .map(|policy| policy.time_to_live(clock::now())))
+    }
 
-fn get_warning_code(res: &Response) -> Option<usize> {
-    res.headers().get("Warning").and_then(|hdr| {
-        hdr.to_str()
-            .ok()
-            .and_then(|s| s.chars().take(3).collect::<String>().parse().ok())
-    })
-}
+    /// Subscribes to a [`CacheEvent`] for every request this cache handles
+    /// from now on, e.g. to feed metrics.
+    ///
+    /// Each subscriber gets a bounded buffer. The cache never waits on a
+    /// slow subscriber: if its buffer is full, new events are dropped for it
+    /// until it catches up. Dropping the stream unsubscribes.
+    pub fn event_stream(&self) -> impl Stream<Item = CacheEvent> {
+        let (sender, receiver) = mpsc::channel(EVENT_BUFFER);
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
 
-fn is_stale(req: &Request, res: &Response) -> bool {
-    // TODO - most of what this looks like is gonna depend on http-cache-semantics
-    unimplemented!()
-}
+    fn emit(&self, event: CacheEvent) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if subscribers.is_empty() {
+            return;
+        }
+        subscribers.retain(|sender| !sender.is_closed());
+        for sender in subscribers.iter_mut() {
+            // A full buffer just means this event gets dropped for that
+            // subscriber.
+            let _ = sender.try_send(event.clone());
+        }
+    }
 
-fn add_warning(uri: &http::Uri, headers: &mut HeaderMap, code: usize, message: &str) {
-    //   Warning    = "Warning" ":" 1#warning-value
-    // warning-value = warn-code SP warn-agent SP warn-text [SP warn-date]
-    // warn-code  = 3DIGIT
-    // warn-agent = ( host [ ":" port ] ) | pseudonym
-    //                 ; the name or pseudonym of the server adding
-    //                 ; the Warning header, for use in debugging
-    // warn-text  = quoted-string
-    // warn-date  = <"> HTTP-date <">
-    // (https://tools.ietf.org/html/rfc2616#section-14.46)
-    //
-    headers.append(
-        "Warning",
-        http::HeaderValue::from_str(
-            format!(
-                "{} {} {:?} \"{}\"",
-                uri.host().expect("Invalid URL"),
-                code,
-                message,
-                httpdate::fmt_http_date(SystemTime::now())
-            )
-            .as_str(),
-        )
-        .expect("Failed to generate warning string"),
-    );
+    pub async fn run<'a, C: HttpClient>(
+        &self,
+        req: Request,
+        client: C,
+        next: Next<'a, C>,
+    ) -> Result<Response, surf::Exception>
+    where
+        T: Sync,
+    {
+        self.run_with(req, &NextFetch { client, next }).await
+    }
+
+    /// Handles `req` like [`Cache::run`], but fetches from `origin` rather
+    /// than a surf middleware chain.
+    pub(crate) async fn run_with(
+        &self,
+        req: Request,
+        origin: &(dyn OriginFetch + Send + Sync),
+    ) -> Result<Response, surf::Exception>
+    where
+        T: Sync,
+    {
+        let req = self.hash_body(req).await?;
+        let key = managers::req_key(None, &self.key_req(&req));
+        let mode = format!("{:?}", self.mode_for(&req));
+        let uri = req.uri().clone();
+        let (mut res, outcome) = self.handle(req, origin).await?;
+        if outcome == Outcome::Stale {
+            //   110 Response is stale
+            //   MUST be included whenever the returned response is stale.
+            // (https://tools.ietf.org/html/rfc2616#section-14.46)
+            self.add_warning(&uri, res.headers_mut(), 110, "Response is stale");
+        }
+        // The policy is only there if a stored entry was served or stored.
+        let age = res
+            .extensions()
+            .get::<CachePolicy>()
+            .map(|policy| policy.age(clock::now()));
+        log::debug!(
+            "{}: mode={} decision={} age={} ttl={} status={}",
+            key,
+            mode,
+            outcome.decision(),
+            match age {
+                Some(age) => format!("{}s", age.as_secs()),
+                None => "-".to_string(),
+            },
+            match outcome {
+                Outcome::Hit(ttl) => format!("{}s", ttl.as_secs()),
+                _ => "-".to_string(),
+            },
+            res.status().as_u16()
+        );
+        self.emit(CacheEvent {
+            key: key.clone(),
+            outcome,
+            status: res.status(),
+        });
+        res.extensions_mut().insert(CacheStatus::from(outcome));
+        if self.cache_status_header {
+            let status = outcome.cache_status(&key, &res);
+            if let Ok(value) = http::HeaderValue::from_str(&status) {
+                res.headers_mut().append("Cache-Status", value);
+            }
+        }
+        if let Some(Hook(on_serve)) = &self.on_serve {
+            match outcome {
+                Outcome::Hit(_) | Outcome::Stale | Outcome::Revalidated => on_serve(&mut res),
+                _ => {}
+            }
+        }
+        Ok(res)
+    }
+
+    async fn handle(
+        &self,
+        req: Request,
+        origin: &(dyn OriginFetch + Send + Sync),
+    ) -> Result<(Response, Outcome), surf::Exception>
+    where
+        T: Sync,
+    {
+        let mode = self.mode_for(&req);
+        // Ranged requests are passed straight through: a stored full
+        // response can't answer them, and the `206 Partial Content` they get
+        // back is never stored since only `200`s are.
+        let is_cacheable_request = self.cacheable_methods.contains(req.method())
+            && !req.headers().contains_key(http::header::RANGE)
+            && !self.is_bypassed(&req);
+        let is_cacheable =
+            is_cacheable_request && *mode != CacheMode::NoStore && *mode != CacheMode::Reload;
+
+        if *mode == CacheMode::Reload && self.reload_fallback_to_cache && is_cacheable_request {
+            return self.reload(req, origin).await;
+        }
+
+        if !is_cacheable {
+            // Nothing stored could answer this request, and it mustn't go
+            // to the origin either.
+            if *mode == CacheMode::OnlyIfCached {
+                return Ok((not_cached_response(&req), Outcome::NotCached));
+            }
+            return Ok((self.remote_fetch(req, origin).await?, Outcome::Bypass));
+        }
+
+        if let Some(CacheEntry {
+            response: mut res,
+            policy,
+            ..
+        }) = self.lookup(&req).await?
+        {
+            strip_stored_warnings(res.headers_mut());
+            res.extensions_mut().insert(policy.clone());
+
+            if *mode == CacheMode::Default && self.is_fresh_enough(&req, &policy, clock::now()) {
+                // The caller already has this response, so there's no need to
+                // send it again.
+                if is_not_modified(&req, &policy) {
+                    res = not_modified_response(&res);
+                    res.extensions_mut().insert(policy.clone());
+                }
+                self.add_ttl_header(&mut res, &policy);
+                Ok((res, Outcome::Hit(policy.time_to_live(clock::now()))))
+            } else if *mode == CacheMode::Default {
+                Ok(self.conditional_fetch(req, res, &policy, origin).await?)
+            } else if *mode == CacheMode::ForceCache || *mode == CacheMode::OnlyIfCached {
+                // Stored responses are served no matter how stale they are,
+                // but only the stale ones need to say so.
+                let outcome = if policy.is_stale(clock::now()) {
+                    //   112 Disconnected operation
+                    // SHOULD be included if the cache is intentionally disconnected from
+                    // the rest of the network for a period of time.
+                    // (https://tools.ietf.org/html/rfc2616#section-14.46)
+                    self.add_warning(req.uri(), res.headers_mut(), 112, "Disconnected operation");
+                    Outcome::Stale
+                } else {
+                    Outcome::Hit(policy.time_to_live(clock::now()))
+                };
+                self.add_ttl_header(&mut res, &policy);
+                Ok((res, outcome))
+            } else {
+                Ok((self.remote_fetch(req, origin).await?, Outcome::Miss))
+            }
+        } else if *mode == CacheMode::OnlyIfCached {
+            Ok((not_cached_response(&req), Outcome::NotCached))
+        } else {
+            Ok((self.remote_fetch(req, origin).await?, Outcome::Miss))
+        }
+    }
+
+    /// Fetches `req` afresh, as `Reload` does, but falls back to whatever is
+    /// stored for it if the origin fails.
+    async fn reload(
+        &self,
+        req: Request,
+        origin: &(dyn OriginFetch + Send + Sync),
+    ) -> Result<(Response, Outcome), surf::Exception>
+    where
+        T: Sync,
+    {
+        let stored_req = clone_req(&req);
+        let fetched = match self.remote_fetch(req, origin).await {
+            Ok(res) if !res.status().is_server_error() => return Ok((res, Outcome::Bypass)),
+            fetched => fetched,
+        };
+        let now = clock::now();
+        // The stored copy is only a fallback, so failing to look it up
+        // mustn't hide what the origin said.
+        let entry = match self.lookup(&stored_req).await {
+            Ok(entry) => entry,
+            Err(e) => {
+                log::warn!(
+                    "Cache lookup failed, passing the origin's response through: {}",
+                    e
+                );
+                None
+            }
+        };
+        match entry {
+            Some(CacheEntry {
+                response: mut res,
+                policy,
+                ..
+            }) if !is_error(policy.status())
+                && (!policy.must_revalidate() || !policy.is_stale(now)) =>
+            {
+                strip_stored_warnings(res.headers_mut());
+                res.extensions_mut().insert(policy.clone());
+                //   111 Revalidation failed
+                //   MUST be included if a cache returns a stale response
+                //   because an attempt to revalidate the response failed,
+                //   due to an inability to reach the server.
+                // (https://tools.ietf.org/html/rfc2616#section-14.46)
+                self.add_warning(
+                    stored_req.uri(),
+                    res.headers_mut(),
+                    111,
+                    "Revalidation failed",
+                );
+                let outcome = if policy.is_stale(now) {
+                    Outcome::Stale
+                } else {
+                    Outcome::Hit(policy.time_to_live(now))
+                };
+                self.add_ttl_header(&mut res, &policy);
+                Ok((res, outcome))
+            }
+            _ => fetched.map(|res| (res, Outcome::Bypass)),
+        }
+    }
+
+    async fn lookup(&self, req: &Request) -> Result<Option<CacheEntry>, surf::Exception> {
+        let mut key_req = self.key_req(req);
+        if let Some(entry) = self.get_entry(&key_req).await? {
+            Ok(Some(entry))
+        } else if req.method() == http::Method::HEAD {
+            // https://tools.ietf.org/html/rfc7234#section-4.3.5
+            //
+            // A stored response to a GET request can be used to answer a HEAD
+            // request for the same resource, minus the body.
+            *key_req.method_mut() = http::Method::GET;
+            Ok(self.get_entry(&key_req).await?.map(|entry| {
+                let (parts, _) = entry.response.into_parts();
+                CacheEntry {
+                    response: http::Response::from_parts(parts, Body::empty()),
+                    ..entry
+                }
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn get_entry(&self, req: &Request) -> Result<Option<CacheEntry>, surf::Exception> {
+        let entry = match self.read_entry(req).await? {
+            // Each encoding of a response that varies on `Accept-Encoding`
+            // has an entry of its own, and the plain entry, which holds the
+            // encoding stored last, lists which ones there are.
+            Some(entry) if !self.ignore_vary && !entry.policy.vary_matches(req) => {
+                let encoding = request_encoding(req);
+                if entry.policy.encodings().contains(&encoding) {
+                    self.read_entry(&self.variant_req(req, encoding)).await?
+                } else {
+                    None
+                }
+            }
+            entry => entry,
+        };
+        match entry {
+            Some(entry) if !self.ignore_vary && !entry.policy.vary_matches(req) => Ok(None),
+            // A bodiless `HEAD` response can't stand in for a full one.
+            Some(entry) if entry.policy.is_head() && req.method() != http::Method::HEAD => Ok(None),
+            entry => Ok(entry),
+        }
+    }
+
+    async fn read_entry(&self, req: &Request) -> Result<Option<CacheEntry>, surf::Exception> {
+        match self.cache_manager.get(req).await {
+            Err(e) if self.fail_open => {
+                log::warn!("Cache lookup failed, treating it as a miss: {}", e);
+                Ok(None)
+            }
+            res => res,
+        }
+    }
+
+    async fn put_entry(
+        &self,
+        req: &Request,
+        mut res: Response,
+        policy: CachePolicy,
+    ) -> Result<Response, surf::Exception> {
+        strip_hop_by_hop(res.headers_mut());
+        // Headers meant for just this caller, like `Set-Cookie`, are kept
+        // out of the store, but the caller still gets them.
+        let mut withheld = Vec::new();
+        for name in &self.strip_headers {
+            for value in res.headers().get_all(name) {
+                withheld.push((name.clone(), value.clone()));
+            }
+            res.headers_mut().remove(name);
+        }
+        // So are the ones the response itself marks `no-cache` or, for
+        // shared caches, `private`.
+        for name in policy.unstorable_headers() {
+            if let Ok(name) = http::header::HeaderName::from_bytes(name.as_bytes()) {
+                for value in res.headers().get_all(&name) {
+                    withheld.push((name.clone(), value.clone()));
+                }
+                res.headers_mut().remove(&name);
+            }
+        }
+        let mut res = if !self.ignore_vary && policy.varies_on_encoding() {
+            self.store_variant(req, res, policy).await?
+        } else {
+            self.store_entry(req, res, policy).await?
+        };
+        for (name, value) in withheld {
+            res.headers_mut().append(name, value);
+        }
+        Ok(res)
+    }
+
+    /// Stores one encoding of a response that varies on `Accept-Encoding`
+    /// under a key of its own, and again under the plain key, listing every
+    /// encoding stored so far.
+    async fn store_variant(
+        &self,
+        req: &Request,
+        res: Response,
+        policy: CachePolicy,
+    ) -> Result<Response, surf::Exception> {
+        let encoding = request_encoding(req);
+        let mut encodings = match self.read_entry(req).await? {
+            Some(entry) if entry.policy.varies_on_encoding() => entry.policy.encodings().clone(),
+            _ => BTreeSet::new(),
+        };
+        encodings.insert(encoding.clone());
+        let policy = policy.with_encodings(encodings);
+        let latency = res.extensions().get::<OriginLatency>().cloned();
+        let variant_req = self.variant_req(req, encoding);
+        let (parts, mut body) = self
+            .store_entry(&variant_req, res, policy.clone())
+            .await?
+            .into_parts();
+        let mut bytes = Vec::new();
+        body.read_to_end(&mut bytes).await?;
+        let mut res = http::Response::new(Body::from(bytes));
+        *res.status_mut() = parts.status;
+        *res.version_mut() = parts.version;
+        *res.headers_mut() = parts.headers;
+        if let Some(latency) = latency {
+            res.extensions_mut().insert(latency);
+        }
+        self.store_entry(req, res, policy).await
+    }
+
+    /// Drops whatever is stored for `req`, along with every encoding of it
+    /// stored separately.
+    async fn delete_entry(&self, req: &Request) -> Result<(), surf::Exception>
+    where
+        T: Sync,
+    {
+        if let Some(policy) = self.cache_manager.get_metadata(req).await? {
+            for encoding in policy.encodings() {
+                self.cache_manager
+                    .delete(&self.variant_req(req, encoding.clone()))
+                    .await?;
+            }
+        }
+        self.cache_manager.delete(req).await
+    }
+
+    async fn store_entry(
+        &self,
+        req: &Request,
+        res: Response,
+        policy: CachePolicy,
+    ) -> Result<Response, surf::Exception> {
+        if !self.fail_open {
+            let mut res = self.cache_manager.put(req, res, policy.clone()).await?;
+            res.extensions_mut().insert(policy);
+            return Ok(res);
+        }
+        // Hang on to the body so the response can still be handed back if
+        // the manager fails to store it. The manager reads it from the same
+        // buffer rather than a copy.
+        let (parts, mut body) = res.into_parts();
+        let mut bytes = Vec::new();
+        body.read_to_end(&mut bytes).await?;
+        let bytes = Arc::new(bytes);
+        let status = parts.status;
+        let version = parts.version;
+        let headers = parts.headers.clone();
+        let res = http::Response::from_parts(parts, shared_body(&bytes));
+        match self.cache_manager.put(req, res, policy.clone()).await {
+            Ok(mut res) => {
+                res.extensions_mut().insert(policy);
+                Ok(res)
+            }
+            Err(e) => {
+                log::warn!(
+                    "Failed to store response in cache, passing it through: {}",
+                    e
+                );
+                let mut res = http::Response::new(shared_body(&bytes));
+                *res.status_mut() = status;
+                *res.version_mut() = version;
+                *res.headers_mut() = headers;
+                Ok(res)
+            }
+        }
+    }
+
+    /// Mode to handle `req` in: the configured one, unless the request asks
+    /// for something stricter itself.
+    fn mode_for(&self, req: &Request) -> &CacheMode {
+        // https://tools.ietf.org/html/rfc7234#section-5.2.1.7
+        //
+        // The "only-if-cached" request directive indicates that the client
+        // only wishes to obtain a stored response.
+        if policy::request_directives(req.headers()).contains_key("only-if-cached") {
+            &CacheMode::OnlyIfCached
+        } else {
+            &self.mode
+        }
+    }
+
+    /// Whether a stored response can be served for `req` as-is, without
+    /// revalidating it first.
+    fn is_fresh_enough(&self, req: &Request, policy: &CachePolicy, now: SystemTime) -> bool {
+        let age = policy.age(now);
+        if let Some(max_served_age) = self.max_served_age {
+            if age > max_served_age {
+                return false;
+            }
+        }
+        let reqcc = policy::request_directives(req.headers());
+        // https://tools.ietf.org/html/rfc7234#section-5.2.1.4
+        //
+        // The "no-cache" request directive indicates that a cache MUST NOT
+        // use a stored response to satisfy the request without successful
+        // validation on the origin server.
+        if reqcc.contains_key("no-cache") {
+            return false;
+        }
+        // https://tools.ietf.org/html/rfc7234#section-5.2.1.1
+        //
+        // The "max-age" request directive indicates that the client is
+        // unwilling to accept a response whose age is greater than the
+        // specified number of seconds.
+        if let Some(Some(max_age)) = reqcc.get("max-age") {
+            if let Ok(max_age) = max_age.parse() {
+                if age > Duration::from_secs(max_age) {
+                    return false;
+                }
+            }
+        }
+        !policy.requires_revalidation() && !policy.is_stale(now)
+    }
+
+    /// Whether `req` matches the configured bypass rule, and should be
+    /// treated as if the mode were `NoStore`.
+    fn is_bypassed(&self, req: &Request) -> bool {
+        self.bypass
+            .as_ref()
+            .map(|Hook(bypass)| bypass(req))
+            .unwrap_or(false)
+    }
+
+    /// Whether the configured `should_cache` hook, if any, lets `res` be
+    /// stored.
+    fn should_cache(&self, req: &Request, res: &Response) -> bool {
+        self.should_cache
+            .as_ref()
+            .map(|Hook(should_cache)| should_cache(req, res))
+            .unwrap_or(true)
+    }
+
+    /// Copy of `req`, minus the body, that's handed to the cache manager in
+    /// place of the original.
+    fn key_req(&self, req: &Request) -> Request {
+        let mut key_req = clone_req(req);
+        if self.normalize_urls {
+            *key_req.uri_mut() = normalize::normalize_uri(req.uri());
+        }
+        if let Some(hash) = req.extensions().get::<BodyHash>() {
+            key_req.extensions_mut().insert(hash.clone());
+        }
+        let partition = match &self.partition {
+            Some(Hook(partition)) => partition(req).map(Partition),
+            None => req.extensions().get::<Partition>().cloned(),
+        };
+        if let Some(partition) = partition {
+            key_req.extensions_mut().insert(partition);
+        }
+        key_req
+    }
+
+    /// Copy of `key_req` for the entry holding the `encoding` variant of a
+    /// response that varies on `Accept-Encoding`.
+    fn variant_req(&self, key_req: &Request, encoding: String) -> Request {
+        let mut variant_req = self.key_req(key_req);
+        variant_req
+            .extensions_mut()
+            .insert(EncodingVariant(encoding));
+        variant_req
+    }
+
+    /// Tags requests whose responses may be cached, but which carry a body,
+    /// with a hash of that body so different payloads sent to the same URL
+    /// end up in different entries.
+    async fn hash_body(&self, req: Request) -> Result<Request, surf::Exception> {
+        let method = req.method();
+        if method == http::Method::GET
+            || method == http::Method::HEAD
+            || !self.cacheable_methods.contains(method)
+        {
+            return Ok(req);
+        }
+        let (mut parts, mut body) = req.into_parts();
+        let mut hasher = Sha256::new();
+        let mut bytes = Vec::new();
+        let mut chunk = [0; 8192];
+        loop {
+            let read = body.read(&mut chunk).await?;
+            if read == 0 {
+                break;
+            }
+            hasher.input(&chunk[..read]);
+            bytes.extend_from_slice(&chunk[..read]);
+        }
+        parts
+            .extensions
+            .insert(BodyHash(format!("{:x}", hasher.result())));
+        Ok(http::Request::from_parts(parts, Body::from(bytes)))
+    }
+
+    fn add_ttl_header(&self, res: &mut Response, policy: &CachePolicy) {
+        if self.ttl_header {
+            let ttl = policy.time_to_live(clock::now()).as_secs();
+            res.headers_mut()
+                .insert("X-Cache-TTL", http::HeaderValue::from(ttl));
+        }
+    }
+
+    fn add_warning(&self, uri: &http::Uri, headers: &mut HeaderMap, code: usize, message: &str) {
+        if self.inject_warnings {
+            // URLs without a host, like `file:` ones, get the `-` pseudonym.
+            let agent = match &self.warn_agent {
+                Some(agent) => agent.as_str(),
+                None => uri.host().filter(|host| !host.is_empty()).unwrap_or("-"),
+            };
+            add_warning(agent, headers, code, message);
+        }
+    }
+
+    async fn conditional_fetch(
+        &self,
+        mut req: Request,
+        mut cached_res: Response,
+        policy: &CachePolicy,
+        origin: &(dyn OriginFetch + Send + Sync),
+    ) -> Result<(Response, Outcome), surf::Exception>
+    where
+        T: Sync,
+    {
+        let copied_req = self.key_req(&req);
+        // Resources that only ever grow, like logs, can be brought up to
+        // date by asking for just the bytes past the end of the stored copy.
+        let stored_body = match range_validator(policy) {
+            Some(validator) if self.range_revalidation => {
+                let (parts, mut body) = cached_res.into_parts();
+                let mut bytes = Vec::new();
+                body.read_to_end(&mut bytes).await?;
+                cached_res = http::Response::from_parts(parts, Body::from(bytes.clone()));
+                req.headers_mut().insert(
+                    http::header::RANGE,
+                    http::HeaderValue::from_str(&format!("bytes={}-", bytes.len()))?,
+                );
+                req.headers_mut().insert(
+                    http::header::IF_RANGE,
+                    http::HeaderValue::from_str(validator)?,
+                );
+                Some(bytes)
+            }
+            _ => {
+                set_revalidation_headers(&mut req, policy);
+                None
+            }
+        };
+        let is_ranged = stored_body.is_some();
+        // A response that's still fresh, but being revalidated anyway, e.g.
+        // because of `no-cache`, can always fall back to the stored copy.
+        let now = clock::now();
+        let can_fall_back = !(policy.must_revalidate() && policy.is_stale(now));
+        let fallback = if policy.is_stale(now) {
+            Outcome::Stale
+        } else {
+            Outcome::Hit(policy.time_to_live(now))
+        };
+        match self.revalidate(req, origin).await {
+            Ok(cond_res) if cond_res.status().is_server_error() && !can_fall_back => {
+                Ok((cond_res, Outcome::Miss))
+            }
+            Ok(cond_res) => {
+                if cond_res.status().is_server_error() {
+                    //   111 Revalidation failed
+                    //   MUST be included if a cache returns a stale response
+                    //   because an attempt to revalidate the response failed,
+                    //   due to an inability to reach the server.
+                    // (https://tools.ietf.org/html/rfc2616#section-14.46)
+                    self.add_warning(
+                        copied_req.uri(),
+                        cached_res.headers_mut(),
+                        111,
+                        "Revalidation failed",
+                    );
+                    Ok((cached_res, fallback))
+                } else if is_ranged && cond_res.status() == http::StatusCode::RANGE_NOT_SATISFIABLE
+                {
+                    // Nothing past the end of the stored body: it's current.
+                    // The 416 describes the failed range rather than the
+                    // stored response, so only the policy is refreshed. Its
+                    // age starts over from the 416, as it would from a 304.
+                    let date = match cond_res.headers().get(http::header::DATE) {
+                        Some(date) => date.clone(),
+                        None => http::HeaderValue::from_str(&httpdate::fmt_http_date(now))?,
+                    };
+                    let headers = cached_res.headers_mut();
+                    headers.insert(http::header::DATE, date);
+                    match cond_res.headers().get(http::header::AGE) {
+                        Some(age) => headers.insert(http::header::AGE, age.clone()),
+                        None => headers.remove(http::header::AGE),
+                    };
+                    let policy = CachePolicy::new_options(&copied_req, &cached_res, self.options);
+                    let mut res = self.put_entry(&copied_req, cached_res, policy).await?;
+                    res.extensions_mut()
+                        .insert(ForwardStatus(cond_res.status()));
+                    Ok((res, Outcome::Revalidated))
+                } else if cond_res.status() == http::StatusCode::NOT_MODIFIED {
+                    // A 304 has no body of its own, so the stored response's
+                    // status and body are carried over. The body is moved
+                    // across as-is rather than read into a string, so binary
+                    // payloads come through revalidation byte-for-byte.
+                    let mut res = http::Response::builder();
+                    res.status(cached_res.status());
+                    res.version(cached_res.version());
+                    let headers = res.headers_mut().expect("Couldn't get headers.");
+                    merge_revalidated_headers(headers, cached_res.headers(), cond_res.headers());
+                    let res = res.body(cached_res.into_body()).unwrap();
+                    let policy = CachePolicy::new_options(&copied_req, &res, self.options);
+                    let mut res = self.put_entry(&copied_req, res, policy).await?;
+                    res.extensions_mut()
+                        .insert(ForwardStatus(cond_res.status()));
+                    Ok((res, Outcome::Revalidated))
+                } else if let (Some(mut body), http::StatusCode::PARTIAL_CONTENT) =
+                    (stored_body, cond_res.status())
+                {
+                    if content_range_start(&cond_res) != Some(body.len() as u64) {
+                        // Not the continuation that was asked for, so there's
+                        // nothing safe to patch the stored copy with.
+                        self.add_warning(
+                            copied_req.uri(),
+                            cached_res.headers_mut(),
+                            111,
+                            "Revalidation failed",
+                        );
+                        return Ok((cached_res, Outcome::Stale));
+                    }
+                    let (parts, mut tail) = cond_res.into_parts();
+                    tail.read_to_end(&mut body).await?;
+                    let mut res = http::Response::new(Body::from(body));
+                    *res.status_mut() = cached_res.status();
+                    *res.version_mut() = cached_res.version();
+                    merge_revalidated_headers(
+                        res.headers_mut(),
+                        cached_res.headers(),
+                        &parts.headers,
+                    );
+                    res.headers_mut().remove(http::header::CONTENT_LENGTH);
+                    let policy = CachePolicy::new_options(&copied_req, &res, self.options);
+                    let mut res = self.put_entry(&copied_req, res, policy).await?;
+                    res.extensions_mut().insert(ForwardStatus(parts.status));
+                    Ok((res, Outcome::Revalidated))
+                } else {
+                    // Anything else is a new response, which `remote_fetch`
+                    // has already stored in place of the old one if it could.
+                    // The stored response wasn't confirmed, so it's a miss.
+                    Ok((cond_res, Outcome::Miss))
+                }
+            }
+            Err(e) => {
+                if !can_fall_back {
+                    Err(e)
+                } else {
+                    let headers = cached_res.headers_mut();
+                    //   111 Revalidation failed
+                    //   MUST be included if a cache returns a stale response
+                    //   because an attempt to revalidate the response failed,
+                    //   due to an inability to reach the server.
+                    // (https://tools.ietf.org/html/rfc2616#section-14.46)
+                    self.add_warning(copied_req.uri(), headers, 111, "Revalidation failed");
+                    //   199 Miscellaneous warning
+                    //   The warning text MAY include arbitrary information to
+                    //   be presented to a human user, or logged. A system
+                    //   receiving this warning MUST NOT take any automated
+                    //   action, besides presenting the warning to the user.
+                    // (https://tools.ietf.org/html/rfc2616#section-14.46)
+                    self.add_warning(
+                        copied_req.uri(),
+                        headers,
+                        199,
+                        format!("Miscellaneous Warning {}", e).as_str(),
+                    );
+
+                    Ok((cached_res, fallback))
+                }
+            }
+        }
+    }
+
+    /// Sends a conditional request upstream, retrying network errors as
+    /// configured by [`CacheBuilder::revalidation_retries`].
+    async fn revalidate(
+        &self,
+        req: Request,
+        origin: &(dyn OriginFetch + Send + Sync),
+    ) -> Result<Response, surf::Exception>
+    where
+        T: Sync,
+    {
+        let mut attempt = 0;
+        loop {
+            let res = self.remote_fetch(clone_req(&req), origin).await;
+            match res {
+                Err(e) if attempt < self.revalidation_retries => {
+                    log::debug!("revalidating {} failed, retrying: {}", req.uri(), e);
+                    Delay::new(backoff(self.revalidation_backoff, attempt)).await;
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    async fn remote_fetch(
+        &self,
+        req: Request,
+        origin: &(dyn OriginFetch + Send + Sync),
+    ) -> Result<Response, surf::Exception>
+    where
+        T: Sync,
+    {
+        let copied_req = self.key_req(&req);
+        let started = clock::now();
+        let mut res = origin.fetch(req).await?;
+        let latency = clock::now().duration_since(started).unwrap_or_default();
+        res.extensions_mut().insert(OriginLatency(latency));
+        self.handle_response(copied_req, res).await
+    }
+
+    /// Drops the stored `GET` responses an unsafe request may have made
+    /// outdated: the one for its own URI, and the ones for its response's
+    /// `Location` and `Content-Location`. One that can't be dropped is
+    /// logged, and doesn't stop the rest from being dropped.
+    async fn invalidate_related(&self, req: &Request, res: &Response) -> Result<(), surf::Exception>
+    where
+        T: Sync,
+    {
+        // https://tools.ietf.org/html/rfc7234#section-4.4
+        //
+        // A cache MUST invalidate the effective Request URI as well as the
+        // URI(s) in the Location and Content-Location response header fields
+        // (if present) when a non-error status code is received in response
+        // to an unsafe request method.
+        //
+        // However, a cache MUST NOT invalidate a URI from a Location or
+        // Content-Location response header field if the host part of that
+        // URI differs from the host part in the effective request URI.
+        if req.method().is_safe()
+            || res.status().is_client_error()
+            || res.status().is_server_error()
+        {
+            return Ok(());
+        }
+        let mut targets = vec![req.uri().clone()];
+        for name in &[http::header::LOCATION, http::header::CONTENT_LOCATION] {
+            if let Some(uri) = res
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|location| same_origin_uri(req.uri(), location))
+            {
+                targets.push(uri);
+            }
+        }
+        for uri in targets {
+            let mut target = clone_req(req);
+            *target.method_mut() = http::Method::GET;
+            *target.uri_mut() = uri;
+            if let Err(e) = self.delete_entry(&self.key_req(&target)).await {
+                log::warn!("Failed to invalidate {}: {}", target.uri(), e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops whatever is stored for `req` after an unsafe request, logging
+    /// failures rather than failing the request if the cache fails open.
+    async fn invalidate_entry(&self, req: &Request) -> Result<(), surf::Exception>
+    where
+        T: Sync,
+    {
+        match self.delete_entry(req).await {
+            Err(e) if self.fail_open => {
+                log::warn!(
+                    "Failed to invalidate cache entry, passing it through: {}",
+                    e
+                );
+                Ok(())
+            }
+            res => res,
+        }
+    }
+
+    /// Whether a response that isn't an error is stored for `req`.
+    async fn has_good_entry(&self, req: &Request) -> Result<bool, surf::Exception> {
+        Ok(self
+            .read_entry(req)
+            .await?
+            .is_some_and(|entry| !is_error(entry.policy.status())))
+    }
+
+    /// Stores, or invalidates, whatever the origin sent back for a request.
+    async fn handle_response(
+        &self,
+        copied_req: Request,
+        res: Response,
+    ) -> Result<Response, surf::Exception>
+    where
+        T: Sync,
+    {
+        let is_method_cacheable = self.cacheable_methods.contains(copied_req.method());
+        let mut policy = CachePolicy::new_options(&copied_req, &res, self.options);
+        let status_ttl = self.status_ttls.get(&res.status()).cloned();
+        if let Some(ttl) = status_ttl {
+            policy = policy.with_ttl_override(ttl);
+        }
+        let is_cacheable = self.mode != CacheMode::NoStore
+            && !self.is_bypassed(&copied_req)
+            && is_method_cacheable
+            && (res.status() == http::StatusCode::OK || status_ttl.is_some())
+            && policy.is_storable()
+            && self.should_cache(&copied_req, &res);
+        if is_cacheable
+            && is_error(res.status().as_u16())
+            && self.has_good_entry(&copied_req).await?
+        {
+            // An error response only ever gets stored because of
+            // `status_ttl`, to shield a struggling origin, and must not push
+            // out a good response that's already there, e.g. when a
+            // revalidation fails.
+            Ok(res)
+        } else if is_cacheable {
+            Ok(self.put_entry(&copied_req, res, policy).await?)
+        } else if !is_method_cacheable {
+            self.invalidate_entry(&copied_req).await?;
+            self.invalidate_related(&copied_req, &res).await?;
+            Ok(res)
+        } else {
+            Ok(res)
+        }
+    }
 }
 
-fn clone_req(req: &Request) -> Request {
-    let mut copied_req = http::Request::new(Body::empty());
-    *copied_req.method_mut() = req.method().clone();
-    *copied_req.uri_mut() = req.uri().clone();
-    *copied_req.headers_mut() = req.headers().clone();
-    *copied_req.version_mut() = req.version().clone();
-    copied_req
+impl Cache<BoxedCacheManager> {
+    /// Creates a cache around a manager chosen at runtime, e.g. from config.
+    pub fn boxed(mode: CacheMode, cache_manager: BoxedCacheManager) -> Self {
+        Cache::new(mode, cache_manager)
+    }
 }
 
-// TODO - Surf needs to resolve an issue with Body not being Sync
-//        Ref: https://github.com/http-rs/surf/issues/97
-// impl<C: HttpClient, T: CacheManager> Middleware<C> for Cache<T> {
-//     fn handle<'a>(
-//         &'a self,
-//         req: Request,
-//         client: C,
-//         next: Next<'a, C>,
-//     ) -> BoxFuture<'a, Result<Response, surf::Exception>> {
-//         Box::pin(async move { Ok(self.run(req, client, next).await?) })
-//     }
-// }
+/// A body read from a buffer that can be shared with other bodies.
+fn shared_body(bytes: &Arc<Vec<u8>>) -> Body {
+    Body::from_reader(futures::io::Cursor::new(SharedBytes(bytes.clone())))
+}
+
+struct SharedBytes(Arc<Vec<u8>>);
+
+impl AsRef<[u8]> for SharedBytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Most times the wait between revalidation retries is doubled.
+const MAX_BACKOFF_DOUBLINGS: u32 = 16;
+
+/// How long to wait before retrying a revalidation for the `attempt`th time.
+fn backoff(base: Duration, attempt: u32) -> Duration {
+    base.checked_mul(2u32.pow(attempt.min(MAX_BACKOFF_DOUBLINGS)))
+        .unwrap_or_else(|| Duration::from_secs(u64::MAX))
+}
+
+/// Whether `status` is a client or server error.
+fn is_error(status: u16) -> bool {
+    status >= 400
+}
+
+/// Whether the caller's own conditional headers show it already holds the
+/// stored response.
+fn is_not_modified(req: &Request, policy: &CachePolicy) -> bool {
+    let stored = policy.response_headers();
+    // https://tools.ietf.org/html/rfc7232#section-3.3
+    //
+    // A recipient MUST ignore If-Modified-Since if the request contains an
+    // If-None-Match header field.
+    if let Some(if_none_match) = req.headers().get(http::header::IF_NONE_MATCH) {
+        let etag = match stored.get("etag") {
+            Some(etag) => etag.trim(),
+            None => return false,
+        };
+        return if_none_match.to_str().ok().is_some_and(|tags| {
+            tags.split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || weak_etag_match(tag, etag))
+        });
+    }
+    let since = req
+        .headers()
+        .get(http::header::IF_MODIFIED_SINCE)
+        .and_then(|since| since.to_str().ok())
+        .and_then(|since| httpdate::parse_http_date(since).ok());
+    let last_modified = stored
+        .get("last-modified")
+        .and_then(|last_modified| httpdate::parse_http_date(last_modified).ok());
+    match (since, last_modified) {
+        (Some(since), Some(last_modified)) => last_modified <= since,
+        _ => false,
+    }
+}
+
+/// Whether two entity-tags match under the weak comparison function, which
+/// `If-None-Match` uses. Validating ranges needs strong comparison instead,
+/// which is why [`range_validator`] never hands out weak tags.
+fn weak_etag_match(a: &str, b: &str) -> bool {
+    // https://tools.ietf.org/html/rfc7232#section-2.3.2
+    //
+    // Weak comparison: two entity-tags are equivalent if their opaque-tags
+    // match character-by-character, regardless of either or both being
+    // tagged as "weak".
+    let opaque = |tag: &str| tag.strip_prefix("W/").unwrap_or(tag).to_string();
+    opaque(a) == opaque(b)
+}
+
+/// The `504 Gateway Timeout` answering `req` when only cached responses
+/// may be used, and none can be.
+fn not_cached_response(req: &Request) -> Response {
+    // https://tools.ietf.org/html/rfc7234#section-5.2.1.7
+    //
+    // If it receives this directive, a cache SHOULD either respond
+    // using a stored response that is consistent with the other
+    // constraints of the request, or respond with a 504 (Gateway
+    // Timeout) status code.
+    let message = format!(
+        "{} {} is not in the cache, and only cached responses may be used",
+        req.method(),
+        req.uri()
+    );
+    let len = message.len();
+    let mut res = http::Response::new(Body::from(message.into_bytes()));
+    *res.status_mut() = http::StatusCode::GATEWAY_TIMEOUT;
+    res.headers_mut().insert(
+        http::header::CONTENT_TYPE,
+        http::HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+    res.headers_mut()
+        .insert(http::header::CONTENT_LENGTH, len.into());
+    res
+}
+
+/// A bodiless `304 Not Modified` standing in for `res`.
+fn not_modified_response(res: &Response) -> Response {
+    let mut not_modified = http::Response::new(Body::empty());
+    *not_modified.status_mut() = http::StatusCode::NOT_MODIFIED;
+    *not_modified.version_mut() = res.version();
+    // https://tools.ietf.org/html/rfc7232#section-4.1
+    //
+    // The server generating a 304 response MUST generate any of the
+    // following header fields that would have been sent in a 200 (OK)
+    // response to the same request: Cache-Control, Content-Location, Date,
+    // ETag, Expires, and Vary.
+    for name in &[
+        http::header::CACHE_CONTROL,
+        http::header::CONTENT_LOCATION,
+        http::header::DATE,
+        http::header::ETAG,
+        http::header::EXPIRES,
+        http::header::VARY,
+    ] {
+        for value in res.headers().get_all(name) {
+            not_modified.headers_mut().append(name, value.clone());
+        }
+    }
+    not_modified
+}
+
+/// Fills `headers` with the stored response's headers, updated with the
+/// ones a `304` came back with. The server may have matched on either
+/// validator it was sent, and the result is the same whichever it was.
+fn merge_revalidated_headers(headers: &mut HeaderMap, stored: &HeaderMap, updated: &HeaderMap) {
+    // https://tools.ietf.org/html/rfc7234#section-4.3.4
+    //
+    // the cache MUST... use other header fields provided in the 304 (Not
+    // Modified) response to replace all instances of the corresponding
+    // header fields in the stored response.
+    *headers = stored.clone();
+    let describes_body = |name: &http::header::HeaderName| {
+        *name == http::header::CONTENT_LENGTH || *name == http::header::CONTENT_RANGE
+    };
+    for name in updated.keys() {
+        if !describes_body(name) {
+            headers.remove(name);
+        }
+    }
+    for (name, value) in updated.iter() {
+        if !describes_body(name) {
+            headers.append(name, value.clone());
+        }
+    }
+}
+
+/// Validator to send in `If-Range` when revalidating a stored response by
+/// range, if it has one usable for that and its origin accepts byte ranges.
+fn range_validator(policy: &CachePolicy) -> Option<&String> {
+    let headers = policy.response_headers();
+    let accepts_ranges = headers
+        .get("accept-ranges")
+        .map(|ranges| ranges.split(',').any(|unit| unit.trim() == "bytes"))
+        .unwrap_or(false);
+    if !accepts_ranges {
+        return None;
+    }
+    // https://tools.ietf.org/html/rfc7233#section-3.2
+    //
+    // A client MUST NOT generate an If-Range header field containing an
+    // entity-tag that is marked as weak.
+    headers
+        .get("etag")
+        .filter(|etag| !etag.starts_with("W/"))
+        .or_else(|| headers.get("last-modified"))
+}
+
+/// First byte position of a `206` response's `Content-Range`.
+fn content_range_start(res: &Response) -> Option<u64> {
+    let range = res
+        .headers()
+        .get(http::header::CONTENT_RANGE)?
+        .to_str()
+        .ok()?;
+    range
+        .trim()
+        .strip_prefix("bytes ")?
+        .split('-')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Turns `req` into a conditional request, using whatever validators the
+/// stored response came with.
+fn set_revalidation_headers(req: &mut Request, policy: &CachePolicy) {
+    // https://tools.ietf.org/html/rfc7232#section-2.4
+    //
+    // HTTP/1.1 clients... SHOULD send both validators in cache validation
+    // requests if both an entity-tag and a Last-Modified value have been
+    // provided by the origin server.
+    let validators = [
+        ("etag", http::header::IF_NONE_MATCH),
+        ("last-modified", http::header::IF_MODIFIED_SINCE),
+    ];
+    for (stored, conditional) in validators.iter() {
+        if let Some(value) = policy.response_headers().get(*stored) {
+            if let Ok(value) = http::HeaderValue::from_str(value) {
+                req.headers_mut().insert(conditional.clone(), value);
+            }
+        }
+    }
+}
+
+/// Drops the warnings a stored response must lose before it's reused.
+fn strip_stored_warnings(headers: &mut HeaderMap) {
+    let date = headers
+        .get(http::header::DATE)
+        .and_then(|date| date.to_str().ok())
+        .and_then(|date| httpdate::parse_http_date(date).ok());
+    let kept: Vec<http::HeaderValue> = headers
+        .get_all("Warning")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(split_warning_values)
+        .filter(|warning| match parse_warning(warning) {
+            // https://tools.ietf.org/html/rfc7234#section-4.3.4
+            //
+            // If a stored response is selected for update, the cache MUST:
+            //
+            // * delete any Warning header fields in the stored response with
+            //   warn-code 1xx (see Section 5.5);
+            //
+            // * retain any Warning header fields in the stored response with
+            //   warn-code 2xx;
+            //
+            Some((code, _)) if (100..200).contains(&code) => false,
+            // https://tools.ietf.org/html/rfc7234#section-5.5
+            //
+            // If a recipient receives one or more warning-values with a
+            // warn-date that is different from the Date header field in the
+            // same message, the recipient MUST delete those warning-values
+            // before storing, forwarding, or using the message.
+            Some((_, Some(warn_date))) => date.is_none_or(|date| date == warn_date),
+            _ => true,
+        })
+        .filter_map(|warning| http::HeaderValue::from_str(warning).ok())
+        .collect();
+    headers.remove("Warning");
+    for warning in kept {
+        headers.append("Warning", warning);
+    }
+}
+
+/// Splits a `Warning` header value into its warning-values, leaving alone
+/// any commas inside their quoted strings.
+fn split_warning_values(value: &str) -> Vec<&str> {
+    let mut warnings = Vec::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    let mut start = 0;
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                warnings.push(value[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    warnings.push(value[start..].trim());
+    warnings.retain(|warning| !warning.is_empty());
+    warnings
+}
+
+/// The warn-code of a single warning-value, along with its warn-date if it
+/// has one.
+fn parse_warning(warning: &str) -> Option<(usize, Option<SystemTime>)> {
+    let code = warning.get(..3)?.parse().ok()?;
+    // warning-value = warn-code SP warn-agent SP warn-text [ SP warn-date ]
+    //
+    // Both warn-text and warn-date are quoted strings, so the date, if
+    // there is one, is the second.
+    let mut quoted = Vec::new();
+    let mut current: Option<String> = None;
+    let mut escaped = false;
+    for c in warning.chars() {
+        if let Some(s) = current.as_mut() {
+            if escaped {
+                escaped = false;
+                s.push(c);
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                quoted.extend(current.take());
+            } else {
+                s.push(c);
+            }
+        } else if c == '"' {
+            current = Some(String::new());
+        }
+    }
+    let date = quoted
+        .get(1)
+        .and_then(|date| httpdate::parse_http_date(date).ok());
+    Some((code, date))
+}
+
+fn add_warning(agent: &str, headers: &mut HeaderMap, code: usize, message: &str) {
+    //   Warning    = "Warning" ":" 1#warning-value
+    // warning-value = warn-code SP warn-agent SP warn-text [SP warn-date]
+    // warn-code  = 3DIGIT
+    // warn-agent = ( host [ ":" port ] ) | pseudonym
+    //                 ; the name or pseudonym of the server adding
+    //                 ; the Warning header, for use in debugging
+    // warn-text  = quoted-string
+    // warn-date  = <"> HTTP-date <">
+    // (https://tools.ietf.org/html/rfc2616#section-14.46)
+    //
+    headers.append(
+        "Warning",
+        http::HeaderValue::from_str(
+            format!(
+                "{} {} {:?} \"{}\"",
+                code,
+                agent,
+                message,
+                httpdate::fmt_http_date(clock::now())
+            )
+            .as_str(),
+        )
+        .expect("Failed to generate warning string"),
+    );
+}
+
+/// Removes headers that only describe the connection the response came in
+/// on, which the stored, fixed-length copy won't be served over.
+fn strip_hop_by_hop(headers: &mut HeaderMap) {
+    // https://tools.ietf.org/html/rfc7230#section-6.1
+    //
+    // When a header field aside from Connection is used to supply control
+    // information for or about the current connection, the sender MUST list
+    // the corresponding field-name within the Connection header field.
+    let listed: Vec<String> = headers
+        .get_all(http::header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect();
+    for name in listed {
+        headers.remove(name.as_str());
+    }
+    for name in &[
+        http::header::CONNECTION,
+        http::header::TRANSFER_ENCODING,
+        http::header::PROXY_AUTHENTICATE,
+        http::header::TE,
+        http::header::TRAILER,
+        http::header::UPGRADE,
+    ] {
+        headers.remove(name);
+    }
+    headers.remove("keep-alive");
+}
+
+/// Resolves `location` against `base`, but only if it ends up on the same
+/// host.
+fn same_origin_uri(base: &http::Uri, location: &str) -> Option<http::Uri> {
+    let location: http::Uri = location.parse().ok()?;
+    if location.host().is_none() {
+        // A relative reference, e.g. `/thing/1`.
+        let mut parts = base.clone().into_parts();
+        parts.path_and_query = Some(location.path_and_query()?.clone());
+        http::Uri::from_parts(parts).ok()
+    } else if location.host() == base.host() {
+        Some(location)
+    } else {
+        None
+    }
+}
+
+/// Normalized `Accept-Encoding` of `req`, empty if it doesn't send one.
+fn request_encoding(req: &Request) -> String {
+    req.headers()
+        .get(http::header::ACCEPT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .map(normalize::normalize_accept_encoding)
+        .unwrap_or_default()
+}
+
+/// Copies everything but the body of `req`.
+fn clone_req(req: &Request) -> Request {
+    let mut copied_req = http::Request::new(Body::empty());
+    *copied_req.method_mut() = req.method().clone();
+    *copied_req.uri_mut() = req.uri().clone();
+    // Cloning the whole map, rather than inserting headers one at a time,
+    // keeps every value of repeated headers like `Accept`, in order.
+    *copied_req.headers_mut() = req.headers().clone();
+    *copied_req.version_mut() = req.version();
+    copied_req
+}
+
+// TODO - Surf needs to resolve an issue with Body not being Sync
+//        Ref: https://github.com/http-rs/surf/issues/97
+// impl<C: HttpClient, T: CacheManager> Middleware<C> for Cache<T> {
+//     fn handle<'a>(
+//         &'a self,
+//         req: Request,
+//         client: C,
+//         next: Next<'a, C>,
+//     ) -> BoxFuture<'a, Result<Response, surf::Exception>> {
+//         Box::pin(async move { Ok(self.run(req, client, next).await?) })
+//     }
+// }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use futures::future::BoxFuture;
+
+    use super::*;
+
+    /// Canned origin that answers every request with `respond`, and keeps a
+    /// copy of each request it's sent.
+    struct MockOrigin<F> {
+        respond: F,
+        requests: Mutex<Vec<Request>>,
+    }
+
+    impl<F> MockOrigin<F>
+    where
+        F: Fn(&Request) -> Result<Response, surf::Exception> + Send + Sync,
+    {
+        fn new(respond: F) -> Self {
+            MockOrigin {
+                respond,
+                requests: Mutex::new(Vec::new()),
+            }
+        }
+
+        fn hits(&self) -> usize {
+            self.requests.lock().unwrap().len()
+        }
+
+        fn last_request(&self) -> Request {
+            clone_req(self.requests.lock().unwrap().last().unwrap())
+        }
+    }
+
+    #[async_trait]
+    impl<F> OriginFetch for MockOrigin<F>
+    where
+        F: Fn(&Request) -> Result<Response, surf::Exception> + Send + Sync,
+    {
+        async fn fetch(&self, req: Request) -> Result<Response, surf::Exception> {
+            self.requests.lock().unwrap().push(clone_req(&req));
+            (self.respond)(&req)
+        }
+    }
+
+    fn request(method: http::Method, uri: &str, headers: &[(&str, &str)]) -> Request {
+        let mut req = http::Request::builder();
+        req.method(method).uri(uri);
+        for (name, value) in headers {
+            req.header(*name, *value);
+        }
+        req.body(Body::empty()).unwrap()
+    }
+
+    fn get(uri: &str) -> Request {
+        request(http::Method::GET, uri, &[])
+    }
+
+    fn response(status: u16, headers: &[(&str, &str)], body: &str) -> Response {
+        let mut res = http::Response::builder();
+        res.status(status);
+        for (name, value) in headers {
+            res.header(*name, *value);
+        }
+        res.body(Body::from(body.as_bytes().to_vec())).unwrap()
+    }
+
+    fn cache_status(res: &Response) -> Option<CacheStatus> {
+        res.extensions().get::<CacheStatus>().cloned()
+    }
+
+    async fn body_string(res: Response) -> String {
+        let mut body = String::new();
+        res.into_body().read_to_string(&mut body).await.unwrap();
+        body
+    }
+
+    /// A manager whose storage is gone: every read and write fails, though
+    /// writes read the body first.
+    #[derive(Debug)]
+    struct BrokenManager;
+
+    #[async_trait]
+    impl CacheManager for BrokenManager {
+        async fn get(&self, _req: &Request) -> Result<Option<CacheEntry>, surf::Exception> {
+            Err("disk on fire".into())
+        }
+
+        async fn put(
+            &self,
+            _req: &Request,
+            res: Response,
+            _policy: CachePolicy,
+        ) -> Result<Response, surf::Exception> {
+            let mut body = Vec::new();
+            res.into_body().read_to_end(&mut body).await?;
+            Err("disk on fire".into())
+        }
+
+        async fn delete(&self, _req: &Request) -> Result<(), surf::Exception> {
+            Err("disk on fire".into())
+        }
+    }
+
+    #[async_attributes::test]
+    async fn stores_a_miss_and_serves_the_next_request_from_cache() {
+        let origin =
+            MockOrigin::new(|_| Ok(response(200, &[("Cache-Control", "max-age=60")], "hello")));
+        let cache = Cache::new(CacheMode::Default, MemoryManager::new());
+
+        let res = cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(cache_status(&res), Some(CacheStatus::Miss));
+        assert_eq!(body_string(res).await, "hello");
+
+        let res = cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(cache_status(&res), Some(CacheStatus::Fresh));
+        assert_eq!(body_string(res).await, "hello");
+        assert_eq!(origin.hits(), 1);
+    }
+
+    #[async_attributes::test]
+    async fn fail_open_passes_responses_through_a_broken_manager() {
+        let origin =
+            MockOrigin::new(|_| Ok(response(200, &[("Cache-Control", "max-age=60")], "hello")));
+        let cache = Cache::new(CacheMode::Default, BrokenManager);
+
+        let res = cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(body_string(res).await, "hello");
+        let res = cache
+            .run_with(
+                request(http::Method::POST, "http://example.com/", &[]),
+                &origin,
+            )
+            .await
+            .unwrap();
+        assert_eq!(body_string(res).await, "hello");
+    }
+
+    #[async_attributes::test]
+    async fn fail_closed_surfaces_a_broken_manager() {
+        let origin =
+            MockOrigin::new(|_| Ok(response(200, &[("Cache-Control", "max-age=60")], "hello")));
+        let cache = CacheBuilder::new(BrokenManager).fail_open(false).build();
+
+        assert!(cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .is_err());
+        assert!(cache
+            .run_with(
+                request(http::Method::POST, "http://example.com/", &[]),
+                &origin
+            )
+            .await
+            .is_err());
+    }
+
+    /// A `MemoryManager` that fails to delete whatever's stored for `GET`
+    /// requests to `uri`.
+    #[derive(Debug)]
+    struct UndeletableManager {
+        inner: MemoryManager,
+        uri: &'static str,
+    }
+
+    #[async_trait]
+    impl CacheManager for UndeletableManager {
+        async fn get(&self, req: &Request) -> Result<Option<CacheEntry>, surf::Exception> {
+            self.inner.get(req).await
+        }
+
+        async fn put(
+            &self,
+            req: &Request,
+            res: Response,
+            policy: CachePolicy,
+        ) -> Result<Response, surf::Exception> {
+            self.inner.put(req, res, policy).await
+        }
+
+        async fn delete(&self, req: &Request) -> Result<(), surf::Exception> {
+            if req.method() == http::Method::GET && req.uri() == self.uri {
+                return Err("read-only".into());
+            }
+            self.inner.delete(req).await
+        }
+    }
+
+    #[async_attributes::test]
+    async fn invalidation_carries_on_past_entries_it_cant_drop() {
+        let origin = MockOrigin::new(|req| {
+            if req.method() == http::Method::POST {
+                Ok(response(201, &[("Location", "/thing/1")], ""))
+            } else {
+                Ok(response(200, &[("Cache-Control", "max-age=60")], "thing"))
+            }
+        });
+        let manager = MemoryManager::new();
+        let cache = CacheBuilder::new(UndeletableManager {
+            inner: manager.clone(),
+            uri: "http://example.com/thing",
+        })
+        .fail_open(false)
+        .build();
+        for uri in &["http://example.com/thing", "http://example.com/thing/1"] {
+            cache.run_with(get(uri), &origin).await.unwrap();
+        }
+
+        let res = cache
+            .run_with(
+                request(http::Method::POST, "http://example.com/thing", &[]),
+                &origin,
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 201);
+        assert!(manager
+            .contains(&get("http://example.com/thing"))
+            .await
+            .unwrap());
+        assert!(!manager
+            .contains(&get("http://example.com/thing/1"))
+            .await
+            .unwrap());
+    }
+
+    #[async_attributes::test]
+    async fn ttl_header_counts_down() {
+        clock::mock::freeze();
+        let origin =
+            MockOrigin::new(|_| Ok(response(200, &[("Cache-Control", "max-age=60")], "hello")));
+        let cache = CacheBuilder::new(MemoryManager::new())
+            .ttl_header(true)
+            .build();
+        cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+
+        for &(elapsed, ttl) in &[(0, "60"), (15, "45"), (44, "1")] {
+            clock::mock::advance(Duration::from_secs(elapsed));
+            let res = cache
+                .run_with(get("http://example.com/"), &origin)
+                .await
+                .unwrap();
+            assert_eq!(res.headers()["X-Cache-TTL"], ttl);
+        }
+    }
+
+    /// A body that panics if anything tries to read it.
+    struct UnreadableBody;
+
+    impl futures::io::AsyncRead for UnreadableBody {
+        fn poll_read(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            _buf: &mut [u8],
+        ) -> std::task::Poll<std::io::Result<usize>> {
+            panic!("the body was read");
+        }
+    }
+
+    #[async_attributes::test]
+    async fn should_cache_vetoes_without_reading_the_body() {
+        let origin = MockOrigin::new(|_| {
+            let mut res = response(
+                200,
+                &[("Cache-Control", "max-age=60"), ("X-Large", "1")],
+                "",
+            );
+            *res.body_mut() = Body::from_reader(UnreadableBody);
+            Ok(res)
+        });
+        let manager = MemoryManager::new();
+        let cache = CacheBuilder::new(manager.clone())
+            .should_cache(|_, res| !res.headers().contains_key("X-Large"))
+            .build();
+
+        let res = cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(cache_status(&res), Some(CacheStatus::Miss));
+        assert!(!manager.contains(&get("http://example.com/")).await.unwrap());
+    }
+
+    /// A body that yields its first chunk, then never finishes.
+    struct StalledBody(Option<&'static [u8]>);
+
+    impl futures::io::AsyncRead for StalledBody {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            buf: &mut [u8],
+        ) -> std::task::Poll<std::io::Result<usize>> {
+            match self.0.take() {
+                Some(chunk) => {
+                    buf[..chunk.len()].copy_from_slice(chunk);
+                    std::task::Poll::Ready(Ok(chunk.len()))
+                }
+                None => std::task::Poll::Pending,
+            }
+        }
+    }
+
+    #[async_attributes::test]
+    async fn dropping_a_request_mid_fetch_stores_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = CACacheManager::new(dir.path().to_str().unwrap());
+        let origin = MockOrigin::new(|_| {
+            let mut res = response(200, &[("Cache-Control", "max-age=60")], "");
+            *res.body_mut() = Body::from_reader(StalledBody(Some(b"half of it")));
+            Ok(res)
+        });
+        let cache = Cache::new(CacheMode::Default, manager.clone());
+
+        let fetch = cache.run_with(get("http://example.com/"), &origin);
+        assert!(async_std::future::timeout(Duration::from_millis(50), fetch)
+            .await
+            .is_err());
+
+        assert_eq!(origin.hits(), 1);
+        assert!(manager
+            .get(&get("http://example.com/"))
+            .await
+            .unwrap()
+            .is_none());
+        // Not even a half-written file left behind.
+        assert!(std::fs::read_dir(dir.path()).unwrap().next().is_none());
+    }
+
+    #[async_attributes::test]
+    async fn revalidation_hands_back_a_changed_response() {
+        let origin = MockOrigin::new(|req| {
+            if req.headers().contains_key(http::header::IF_NONE_MATCH) {
+                Ok(response(
+                    200,
+                    &[("ETag", "\"2\""), ("Cache-Control", "no-cache")],
+                    "new",
+                ))
+            } else {
+                Ok(response(
+                    200,
+                    &[("ETag", "\"1\""), ("Cache-Control", "no-cache")],
+                    "old",
+                ))
+            }
+        });
+        let manager = MemoryManager::new();
+        let cache = Cache::new(CacheMode::Default, manager.clone());
+        cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+
+        let res = cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(cache_status(&res), Some(CacheStatus::Miss));
+        assert_eq!(res.headers()["ETag"], "\"2\"");
+        assert_eq!(body_string(res).await, "new");
+        assert_eq!(origin.hits(), 2);
+
+        let stored = manager
+            .get(&get("http://example.com/"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.response.headers()["ETag"], "\"2\"");
+        assert_eq!(body_string(stored.response).await, "new");
+    }
+
+    #[async_attributes::test]
+    async fn ignore_cargo_cult_lets_a_must_revalidate_response_be_served_on_error() {
+        let origin = MockOrigin::new(|req| {
+            if req.headers().contains_key(http::header::IF_NONE_MATCH) {
+                Ok(response(503, &[], ""))
+            } else {
+                Ok(response(
+                    200,
+                    &[
+                        ("ETag", "\"1\""),
+                        (
+                            "Cache-Control",
+                            "max-age=0, must-revalidate, pre-check=0, post-check=0",
+                        ),
+                    ],
+                    "stored",
+                ))
+            }
+        });
+        let cache = CacheBuilder::new(MemoryManager::new())
+            .ignore_cargo_cult(true)
+            .build();
+        cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+
+        let res = cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(body_string(res).await, "stored");
+    }
+
+    fn encoded_origin() -> MockOrigin<impl Fn(&Request) -> Result<Response, surf::Exception>> {
+        MockOrigin::new(|req| {
+            let gzip = req
+                .headers()
+                .get(http::header::ACCEPT_ENCODING)
+                .is_some_and(|value| value.to_str().unwrap().contains("gzip"));
+            let mut headers = vec![("Cache-Control", "max-age=60"), ("Vary", "Accept-Encoding")];
+            if gzip {
+                headers.push(("Content-Encoding", "gzip"));
+            }
+            Ok(response(
+                200,
+                &headers,
+                if gzip { "gzipped" } else { "plain" },
+            ))
+        })
+    }
+
+    #[async_attributes::test]
+    async fn stores_each_encoding_separately() {
+        let origin = encoded_origin();
+        let cache = Cache::new(CacheMode::Default, MemoryManager::new());
+        let gzip = || {
+            request(
+                http::Method::GET,
+                "http://example.com/",
+                &[("Accept-Encoding", "gzip, br")],
+            )
+        };
+        let identity = || {
+            request(
+                http::Method::GET,
+                "http://example.com/",
+                &[("Accept-Encoding", "identity")],
+            )
+        };
+
+        cache.run_with(gzip(), &origin).await.unwrap();
+        cache.run_with(identity(), &origin).await.unwrap();
+        assert_eq!(origin.hits(), 2);
+
+        let res = cache.run_with(gzip(), &origin).await.unwrap();
+        assert_eq!(cache_status(&res), Some(CacheStatus::Fresh));
+        assert_eq!(body_string(res).await, "gzipped");
+        let reordered = request(
+            http::Method::GET,
+            "http://example.com/",
+            &[("Accept-Encoding", "br;q=0.5, GZIP")],
+        );
+        let res = cache.run_with(reordered, &origin).await.unwrap();
+        assert_eq!(cache_status(&res), Some(CacheStatus::Fresh));
+        assert_eq!(body_string(res).await, "gzipped");
+        let res = cache.run_with(identity(), &origin).await.unwrap();
+        assert_eq!(cache_status(&res), Some(CacheStatus::Fresh));
+        assert_eq!(body_string(res).await, "plain");
+        assert_eq!(origin.hits(), 2);
+    }
+
+    #[async_attributes::test]
+    async fn invalidate_drops_every_encoding() {
+        let origin = encoded_origin();
+        let manager = MemoryManager::new();
+        let cache = Cache::new(CacheMode::Default, manager.clone());
+        let gzip = || {
+            request(
+                http::Method::GET,
+                "http://example.com/",
+                &[("Accept-Encoding", "gzip")],
+            )
+        };
+        let identity = || {
+            request(
+                http::Method::GET,
+                "http://example.com/",
+                &[("Accept-Encoding", "identity")],
+            )
+        };
+        cache.run_with(gzip(), &origin).await.unwrap();
+        cache.run_with(identity(), &origin).await.unwrap();
+
+        let url = surf::url::Url::parse("http://example.com/").unwrap();
+        assert!(cache
+            .time_to_live(http::Method::GET, url.clone())
+            .await
+            .unwrap()
+            .is_some());
+        cache
+            .invalidate(http::Method::GET, url.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            cache.time_to_live(http::Method::GET, url).await.unwrap(),
+            None
+        );
+
+        let res = cache.run_with(gzip(), &origin).await.unwrap();
+        assert_eq!(cache_status(&res), Some(CacheStatus::Miss));
+        let res = cache.run_with(identity(), &origin).await.unwrap();
+        assert_eq!(cache_status(&res), Some(CacheStatus::Miss));
+    }
+
+    #[async_attributes::test]
+    async fn shares_responses_that_dont_vary_on_encoding() {
+        let origin =
+            MockOrigin::new(|_| Ok(response(200, &[("Cache-Control", "max-age=60")], "same")));
+        let cache = Cache::new(CacheMode::Default, MemoryManager::new());
+        let gzip = request(
+            http::Method::GET,
+            "http://example.com/",
+            &[("Accept-Encoding", "gzip")],
+        );
+        let br = request(
+            http::Method::GET,
+            "http://example.com/",
+            &[("Accept-Encoding", "br")],
+        );
+
+        cache.run_with(gzip, &origin).await.unwrap();
+        let res = cache.run_with(br, &origin).await.unwrap();
+        assert_eq!(cache_status(&res), Some(CacheStatus::Fresh));
+        assert_eq!(origin.hits(), 1);
+    }
+
+    #[async_attributes::test]
+    async fn unsafe_requests_invalidate_every_encoding() {
+        let origin = encoded_origin();
+        let cache = Cache::new(CacheMode::Default, MemoryManager::new());
+        let gzip = || {
+            request(
+                http::Method::GET,
+                "http://example.com/",
+                &[("Accept-Encoding", "gzip")],
+            )
+        };
+        cache.run_with(gzip(), &origin).await.unwrap();
+
+        let post = request(
+            http::Method::POST,
+            "http://example.com/",
+            &[("Accept-Encoding", "br")],
+        );
+        cache.run_with(post, &origin).await.unwrap();
+
+        let res = cache.run_with(gzip(), &origin).await.unwrap();
+        assert_eq!(cache_status(&res), Some(CacheStatus::Miss));
+    }
+
+    fn ranged_cache() -> Cache<MemoryManager> {
+        CacheBuilder::new(MemoryManager::new())
+            .range_revalidation(true)
+            .build()
+    }
+
+    fn log_response(body: &str) -> Response {
+        response(
+            200,
+            &[
+                ("Cache-Control", "max-age=0"),
+                ("Accept-Ranges", "bytes"),
+                ("ETag", "\"log\""),
+                ("Content-Type", "text/plain"),
+            ],
+            body,
+        )
+    }
+
+    #[async_attributes::test]
+    async fn range_revalidation_appends_the_new_bytes() {
+        let origin = MockOrigin::new(|req| {
+            if req.headers().contains_key(http::header::RANGE) {
+                assert_eq!(req.headers()[http::header::RANGE], "bytes=6-");
+                assert_eq!(req.headers()[http::header::IF_RANGE], "\"log\"");
+                Ok(response(
+                    206,
+                    &[
+                        ("Content-Range", "bytes 6-11/12"),
+                        ("Link", "</a>; rel=a"),
+                        ("Link", "</b>; rel=b"),
+                    ],
+                    "line2\n",
+                ))
+            } else {
+                Ok(log_response("line1\n"))
+            }
+        });
+        let cache = ranged_cache();
+        cache
+            .run_with(get("http://example.com/log"), &origin)
+            .await
+            .unwrap();
+
+        let res = cache
+            .run_with(get("http://example.com/log"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(cache_status(&res), Some(CacheStatus::Revalidated));
+        assert!(!res.headers().contains_key(http::header::CONTENT_RANGE));
+        assert_eq!(res.headers().get_all("Link").iter().count(), 2);
+        assert_eq!(body_string(res).await, "line1\nline2\n");
+    }
+
+    #[async_attributes::test]
+    async fn range_revalidation_keeps_the_stored_headers_on_416() {
+        let origin = MockOrigin::new(|req| {
+            if req.headers().contains_key(http::header::RANGE) {
+                Ok(response(
+                    416,
+                    &[
+                        ("Content-Range", "bytes */6"),
+                        ("Content-Type", "text/html"),
+                    ],
+                    "",
+                ))
+            } else {
+                Ok(log_response("line1\n"))
+            }
+        });
+        let cache = ranged_cache();
+        cache
+            .run_with(get("http://example.com/log"), &origin)
+            .await
+            .unwrap();
+
+        let res = cache
+            .run_with(get("http://example.com/log"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(cache_status(&res), Some(CacheStatus::Revalidated));
+        assert_eq!(res.headers()[http::header::CONTENT_TYPE], "text/plain");
+        assert!(!res.headers().contains_key(http::header::CONTENT_RANGE));
+        assert_eq!(body_string(res).await, "line1\n");
+    }
+
+    #[async_attributes::test]
+    async fn range_revalidation_restarts_the_entrys_age_on_416() {
+        clock::mock::freeze();
+        let origin = MockOrigin::new(|req| {
+            let date = httpdate::fmt_http_date(clock::now());
+            if req.headers().contains_key(http::header::RANGE) {
+                Ok(response(
+                    416,
+                    &[("Content-Range", "bytes */6"), ("Date", &date)],
+                    "",
+                ))
+            } else {
+                Ok(response(
+                    200,
+                    &[
+                        ("Cache-Control", "max-age=60"),
+                        ("Accept-Ranges", "bytes"),
+                        ("ETag", "\"log\""),
+                        ("Date", &date),
+                    ],
+                    "line1\n",
+                ))
+            }
+        });
+        let cache = ranged_cache();
+        cache
+            .run_with(get("http://example.com/log"), &origin)
+            .await
+            .unwrap();
+
+        clock::mock::advance(Duration::from_secs(120));
+        let res = cache
+            .run_with(get("http://example.com/log"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(cache_status(&res), Some(CacheStatus::Revalidated));
+        assert_eq!(origin.hits(), 2);
+
+        let res = cache
+            .run_with(get("http://example.com/log"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(cache_status(&res), Some(CacheStatus::Fresh));
+        assert_eq!(body_string(res).await, "line1\n");
+        assert_eq!(origin.hits(), 2);
+    }
+
+    #[async_attributes::test]
+    async fn range_revalidation_replaces_the_stored_copy_on_200() {
+        let origin = MockOrigin::new(|req| {
+            if req.headers().contains_key(http::header::RANGE) {
+                Ok(log_response("rotated\n"))
+            } else {
+                Ok(log_response("line1\n"))
+            }
+        });
+        let manager = MemoryManager::new();
+        let cache = CacheBuilder::new(manager.clone())
+            .range_revalidation(true)
+            .build();
+        cache
+            .run_with(get("http://example.com/log"), &origin)
+            .await
+            .unwrap();
+
+        let res = cache
+            .run_with(get("http://example.com/log"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(body_string(res).await, "rotated\n");
+        let stored = manager
+            .get(&get("http://example.com/log"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(body_string(stored.response).await, "rotated\n");
+    }
+
+    #[async_attributes::test]
+    async fn retries_a_failed_revalidation() {
+        let failures = AtomicUsize::new(0);
+        let origin = MockOrigin::new(|req| {
+            if !req.headers().contains_key(http::header::IF_NONE_MATCH) {
+                Ok(response(
+                    200,
+                    &[("cache-control", "max-age=0"), ("etag", "\"v1\"")],
+                    "hello",
+                ))
+            } else if failures.fetch_add(1, Ordering::SeqCst) == 0 {
+                Err("connection reset".into())
+            } else {
+                Ok(response(304, &[], ""))
+            }
+        });
+        let cache = CacheBuilder::new(MemoryManager::new())
+            .revalidation_retries(2, Duration::from_millis(1))
+            .build();
+        cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+
+        let res = cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(cache_status(&res), Some(CacheStatus::Revalidated));
+        assert_eq!(body_string(res).await, "hello");
+        assert_eq!(origin.hits(), 3);
+    }
+
+    #[test]
+    fn backoff_saturates_instead_of_overflowing() {
+        assert_eq!(
+            backoff(Duration::from_millis(100), 3),
+            Duration::from_millis(800)
+        );
+        assert_eq!(
+            backoff(Duration::from_millis(100), 40),
+            backoff(Duration::from_millis(100), MAX_BACKOFF_DOUBLINGS)
+        );
+        assert_eq!(
+            backoff(Duration::from_secs(u64::MAX / 2), 5),
+            Duration::from_secs(u64::MAX)
+        );
+    }
+
+    #[async_attributes::test]
+    async fn status_ttl_caches_errors_briefly() {
+        let origin = MockOrigin::new(|_| Ok(response(503, &[], "down")));
+        let cache = CacheBuilder::new(MemoryManager::new())
+            .status_ttl(
+                http::StatusCode::SERVICE_UNAVAILABLE,
+                Duration::from_secs(10),
+            )
+            .build();
+        cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+
+        let res = cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 503);
+        assert_eq!(cache_status(&res), Some(CacheStatus::Fresh));
+        assert_eq!(origin.hits(), 1);
+    }
+
+    #[async_attributes::test]
+    async fn status_ttl_errors_never_replace_a_good_entry() {
+        let origin = MockOrigin::new(|req| {
+            if req.headers().contains_key(http::header::IF_NONE_MATCH) {
+                Ok(response(503, &[], "down"))
+            } else {
+                Ok(response(
+                    200,
+                    &[("Cache-Control", "no-cache"), ("ETag", "\"1\"")],
+                    "up",
+                ))
+            }
+        });
+        let manager = MemoryManager::new();
+        let cache = CacheBuilder::new(manager.clone())
+            .status_ttl(
+                http::StatusCode::SERVICE_UNAVAILABLE,
+                Duration::from_secs(10),
+            )
+            .build();
+        cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+
+        let res = cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        let stored = manager
+            .get(&get("http://example.com/"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.response.status(), 200);
+    }
+
+    #[async_attributes::test]
+    async fn reload_hands_back_the_origins_error_when_the_lookup_fails() {
+        let origin = MockOrigin::new(|_| Err("connection refused".into()));
+        for &fail_open in &[true, false] {
+            let cache = CacheBuilder::new(BrokenManager)
+                .mode(CacheMode::Reload)
+                .reload_fallback_to_cache(true)
+                .fail_open(fail_open)
+                .build();
+
+            let err = cache
+                .run_with(get("http://example.com/"), &origin)
+                .await
+                .unwrap_err();
+            assert_eq!(err.to_string(), "connection refused");
+        }
+    }
+
+    #[async_attributes::test]
+    async fn reload_never_falls_back_to_the_error_it_just_stored() {
+        let origin = MockOrigin::new(|_| Ok(response(503, &[], "down")));
+        let cache = CacheBuilder::new(MemoryManager::new())
+            .mode(CacheMode::Reload)
+            .reload_fallback_to_cache(true)
+            .status_ttl(
+                http::StatusCode::SERVICE_UNAVAILABLE,
+                Duration::from_secs(10),
+            )
+            .build();
+
+        let res = cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 503);
+        assert_eq!(cache_status(&res), Some(CacheStatus::Miss));
+        assert!(!res.headers().contains_key(http::header::WARNING));
+    }
+
+    #[async_attributes::test]
+    async fn warnings_name_their_agent_after_the_code() {
+        let origin = MockOrigin::new(|_| Ok(response(200, &[("Cache-Control", "max-age=0")], "")));
+        let cache = Cache::new(CacheMode::ForceCache, MemoryManager::new());
+        cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+
+        let res = cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        let warning = res.headers()[http::header::WARNING].to_str().unwrap();
+        assert!(warning.starts_with("112 example.com \"Disconnected operation\" \""));
+
+        let cache = CacheBuilder::new(MemoryManager::new())
+            .mode(CacheMode::ForceCache)
+            .warn_agent("edge-cache")
+            .build();
+        cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        let res = cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        let warning = res.headers()[http::header::WARNING].to_str().unwrap();
+        assert!(warning.starts_with("112 edge-cache "));
+    }
+
+    #[async_attributes::test]
+    async fn warn_agents_are_kept_to_one_header_safe_token() {
+        let origin = MockOrigin::new(|_| Ok(response(200, &[("Cache-Control", "max-age=0")], "")));
+        let cache = CacheBuilder::new(MemoryManager::new())
+            .mode(CacheMode::ForceCache)
+            .warn_agent("edge\ncache é")
+            .build();
+        cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+
+        let res = cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        let warning = res.headers()[http::header::WARNING].to_str().unwrap();
+        assert!(warning.starts_with("112 edge-cache-- "));
+    }
+
+    #[async_attributes::test]
+    async fn warnings_for_hostless_urls_use_a_pseudonym() {
+        let origin = MockOrigin::new(|_| Ok(response(200, &[("Cache-Control", "max-age=0")], "")));
+        let cache = Cache::new(CacheMode::ForceCache, MemoryManager::new());
+        cache.run_with(get("/thing"), &origin).await.unwrap();
+
+        let res = cache.run_with(get("/thing"), &origin).await.unwrap();
+        let warning = res.headers()[http::header::WARNING].to_str().unwrap();
+        assert!(warning.starts_with("112 - \"Disconnected operation\" \""));
+    }
+
+    #[test]
+    fn parses_the_warnings_it_adds() {
+        let mut headers = HeaderMap::new();
+        add_warning("example.com", &mut headers, 110, "Response is stale");
+        let warning = headers[http::header::WARNING].to_str().unwrap();
+        let (code, date) = parse_warning(warning).unwrap();
+        assert_eq!(code, 110);
+        assert!(date.is_some());
+    }
+
+    #[test]
+    fn strips_warnings_whose_date_doesnt_match() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            http::header::DATE,
+            "Tue, 15 Nov 1994 08:12:31 GMT".parse().unwrap(),
+        );
+        headers.append(
+            http::header::WARNING,
+            "299 origin \"Matching\" \"Tue, 15 Nov 1994 08:12:31 GMT\""
+                .parse()
+                .unwrap(),
+        );
+        headers.append(
+            http::header::WARNING,
+            "299 origin \"Mismatched\" \"Wed, 16 Nov 1994 08:12:31 GMT\", 214 origin \"Undated\""
+                .parse()
+                .unwrap(),
+        );
+        add_warning("example.com", &mut headers, 110, "Response is stale");
+
+        strip_stored_warnings(&mut headers);
+        let kept: Vec<_> = headers
+            .get_all(http::header::WARNING)
+            .iter()
+            .map(|value| value.to_str().unwrap())
+            .collect();
+        assert_eq!(
+            kept,
+            vec![
+                "299 origin \"Matching\" \"Tue, 15 Nov 1994 08:12:31 GMT\"",
+                "214 origin \"Undated\"",
+            ]
+        );
+    }
+
+    #[async_attributes::test]
+    async fn boxed_caches_work_with_whichever_manager_is_picked() {
+        let dir = tempfile::tempdir().unwrap();
+        for &on_disk in &[true, false] {
+            let manager: BoxedCacheManager = if on_disk {
+                Box::new(CACacheManager::new(dir.path().to_str().unwrap()))
+            } else {
+                Box::new(MemoryManager::new())
+            };
+            let cache = Cache::boxed(CacheMode::Default, manager);
+            let origin =
+                MockOrigin::new(|_| Ok(response(200, &[("Cache-Control", "max-age=60")], "hello")));
+
+            for _ in 0..2 {
+                let res = cache
+                    .run_with(get("http://example.com/"), &origin)
+                    .await
+                    .unwrap();
+                assert_eq!(body_string(res).await, "hello");
+            }
+            assert_eq!(origin.hits(), 1);
+        }
+    }
+
+    #[async_attributes::test]
+    async fn revalidates_no_cache_responses_even_while_fresh() {
+        let origin = MockOrigin::new(|req| {
+            if req.headers().contains_key(http::header::IF_NONE_MATCH) {
+                Ok(response(304, &[("ETag", "\"1\"")], ""))
+            } else {
+                Ok(response(
+                    200,
+                    &[
+                        ("Cache-Control", "no-cache, max-age=3600"),
+                        ("ETag", "\"1\""),
+                    ],
+                    "hello",
+                ))
+            }
+        });
+        let cache = Cache::new(CacheMode::Default, MemoryManager::new());
+        cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+
+        let res = cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(origin.hits(), 2);
+        assert_eq!(origin.last_request().headers()["If-None-Match"], "\"1\"");
+        assert_eq!(cache_status(&res), Some(CacheStatus::Revalidated));
+        assert_eq!(body_string(res).await, "hello");
+    }
+
+    #[cfg(feature = "test-util")]
+    #[async_attributes::test]
+    async fn mock_manager_shows_a_hit_stores_nothing() {
+        let origin =
+            MockOrigin::new(|_| Ok(response(200, &[("Cache-Control", "max-age=60")], "hello")));
+        let manager = MockManager::new();
+        let cache = Cache::new(CacheMode::Default, manager.clone());
+        let key = managers::req_key(None, &get("http://example.com/"));
+        cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(
+            manager.calls(),
+            vec![MockCall::Get(key.clone()), MockCall::Put(key.clone())]
+        );
+        manager.clear_calls();
+
+        cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(manager.calls(), vec![MockCall::Get(key)]);
+    }
+
+    #[async_attributes::test]
+    async fn answers_head_from_a_stored_get() {
+        let origin =
+            MockOrigin::new(|_| Ok(response(200, &[("Cache-Control", "max-age=60")], "hello")));
+        let cache = Cache::new(CacheMode::Default, MemoryManager::new());
+        cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+
+        let res = cache
+            .run_with(
+                request(http::Method::HEAD, "http://example.com/", &[]),
+                &origin,
+            )
+            .await
+            .unwrap();
+        assert_eq!(origin.hits(), 1);
+        assert_eq!(cache_status(&res), Some(CacheStatus::Fresh));
+        assert_eq!(res.headers()["Content-Length"], "5");
+        assert_eq!(body_string(res).await, "");
+    }
+
+    #[async_attributes::test]
+    async fn normalize_urls_shares_entries_between_reordered_queries() {
+        for &(normalize, hits) in &[(true, 1), (false, 2)] {
+            let origin =
+                MockOrigin::new(|_| Ok(response(200, &[("Cache-Control", "max-age=60")], "hello")));
+            let cache = CacheBuilder::new(MemoryManager::new())
+                .normalize_urls(normalize)
+                .build();
+            for uri in &[
+                "http://example.com/?b=2&a=1",
+                "http://EXAMPLE.com:80/?a=1&b=2",
+            ] {
+                cache.run_with(get(uri), &origin).await.unwrap();
+            }
+            assert_eq!(origin.hits(), hits);
+            // The origin sees URLs as they were asked for.
+            let expected = if normalize { "b=2&a=1" } else { "a=1&b=2" };
+            assert_eq!(origin.last_request().uri().query(), Some(expected));
+        }
+    }
+
+    #[async_attributes::test]
+    async fn inject_warnings_off_leaves_stale_responses_unmarked() {
+        let origin = MockOrigin::new(|_| Ok(response(200, &[("Cache-Control", "max-age=0")], "")));
+        let cache = CacheBuilder::new(MemoryManager::new())
+            .mode(CacheMode::ForceCache)
+            .inject_warnings(false)
+            .build();
+        cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+
+        let res = cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(origin.hits(), 1);
+        assert_eq!(cache_status(&res), Some(CacheStatus::Stale));
+        assert!(!res.headers().contains_key(http::header::WARNING));
+    }
+
+    #[async_attributes::test]
+    async fn range_requests_go_straight_to_the_origin() {
+        let origin = MockOrigin::new(|req| {
+            if req.headers().contains_key(http::header::RANGE) {
+                Ok(response(
+                    206,
+                    &[
+                        ("Cache-Control", "max-age=60"),
+                        ("Content-Range", "bytes 0-1/5"),
+                    ],
+                    "he",
+                ))
+            } else {
+                Ok(response(200, &[("Cache-Control", "max-age=60")], "hello"))
+            }
+        });
+        let manager = MemoryManager::new();
+        let cache = Cache::new(CacheMode::Default, manager.clone());
+        let ranged = || {
+            request(
+                http::Method::GET,
+                "http://example.com/",
+                &[("Range", "bytes=0-1")],
+            )
+        };
+
+        let res = cache.run_with(ranged(), &origin).await.unwrap();
+        assert_eq!(res.status(), 206);
+        assert!(!manager.contains(&get("http://example.com/")).await.unwrap());
+
+        // Even with the full response stored, ranges are left to the origin.
+        cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        let res = cache.run_with(ranged(), &origin).await.unwrap();
+        assert_eq!(res.status(), 206);
+        assert_eq!(body_string(res).await, "he");
+        assert_eq!(origin.hits(), 3);
+    }
+
+    /// A client that answers every request with a fresh `hello`.
+    #[derive(Debug, Clone)]
+    struct HelloClient;
+
+    impl HttpClient for HelloClient {
+        type Error = std::io::Error;
+
+        fn send(&self, _req: Request) -> BoxFuture<'static, Result<Response, Self::Error>> {
+            let res = response(200, &[("Cache-Control", "max-age=60")], "hello");
+            Box::pin(async move { Ok(res) })
+        }
+    }
+
+    #[async_attributes::test]
+    async fn prefetched_responses_are_served_from_cache() {
+        let origin = MockOrigin::new(|_| Ok(response(500, &[], "")));
+        let cache = Cache::new(CacheMode::Default, MemoryManager::new());
+        cache
+            .prefetch(get("http://example.com/"), HelloClient)
+            .await
+            .unwrap();
+
+        let res = cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(origin.hits(), 0);
+        assert_eq!(cache_status(&res), Some(CacheStatus::Fresh));
+        assert_eq!(body_string(res).await, "hello");
+    }
+
+    #[async_attributes::test]
+    async fn never_stores_responses_that_vary_on_everything() {
+        let origin = MockOrigin::new(|_| {
+            Ok(response(
+                200,
+                &[("Cache-Control", "max-age=60"), ("Vary", "*")],
+                "hello",
+            ))
+        });
+        let manager = MemoryManager::new();
+        let cache = Cache::new(CacheMode::Default, manager.clone());
+        for _ in 0..2 {
+            let res = cache
+                .run_with(get("http://example.com/"), &origin)
+                .await
+                .unwrap();
+            assert_eq!(cache_status(&res), Some(CacheStatus::Miss));
+        }
+        assert_eq!(origin.hits(), 2);
+        assert!(!manager.contains(&get("http://example.com/")).await.unwrap());
+    }
+
+    #[test]
+    fn clone_req_keeps_every_value_of_a_header() {
+        let req = request(
+            http::Method::GET,
+            "http://example.com/",
+            &[("Accept", "text/html"), ("Accept", "application/json")],
+        );
+        let cloned = clone_req(&req);
+        let accepts: Vec<_> = cloned.headers().get_all("accept").iter().collect();
+        assert_eq!(accepts, vec!["text/html", "application/json"]);
+    }
+
+    #[async_attributes::test]
+    async fn force_cache_serves_whatever_is_stored_and_fetches_the_rest() {
+        let origin = MockOrigin::new(|req| {
+            let max_age = if req.uri().path() == "/fresh" {
+                "max-age=60"
+            } else {
+                "max-age=0"
+            };
+            Ok(response(200, &[("Cache-Control", max_age)], "hello"))
+        });
+        let cache = Cache::new(CacheMode::ForceCache, MemoryManager::new());
+
+        // Nothing stored yet, so both go to the origin.
+        for uri in &["http://example.com/fresh", "http://example.com/stale"] {
+            let res = cache.run_with(get(uri), &origin).await.unwrap();
+            assert_eq!(cache_status(&res), Some(CacheStatus::Miss));
+        }
+        assert_eq!(origin.hits(), 2);
+
+        let res = cache
+            .run_with(get("http://example.com/fresh"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(cache_status(&res), Some(CacheStatus::Fresh));
+        let res = cache
+            .run_with(get("http://example.com/stale"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(cache_status(&res), Some(CacheStatus::Stale));
+        assert_eq!(body_string(res).await, "hello");
+        assert_eq!(origin.hits(), 2);
+    }
+
+    fn post(uri: &str, body: &str) -> Request {
+        http::Request::post(uri)
+            .body(Body::from(body.as_bytes().to_vec()))
+            .unwrap()
+    }
+
+    #[async_attributes::test]
+    async fn serves_a_repeated_post_from_cache_when_opted_in() {
+        let origin =
+            MockOrigin::new(|_| Ok(response(200, &[("Cache-Control", "max-age=60")], "result")));
+        let cache = CacheBuilder::new(MemoryManager::new())
+            .cacheable_methods(vec![http::Method::GET, http::Method::POST])
+            .build();
+        cache
+            .run_with(post("http://example.com/search", "q=1"), &origin)
+            .await
+            .unwrap();
+
+        let res = cache
+            .run_with(post("http://example.com/search", "q=1"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(origin.hits(), 1);
+        assert_eq!(cache_status(&res), Some(CacheStatus::Fresh));
+        assert_eq!(body_string(res).await, "result");
+    }
+
+    #[async_attributes::test]
+    async fn keeps_posts_with_different_bodies_apart() {
+        let origin = MockOrigin::new(|_| Ok(response(200, &[("Cache-Control", "max-age=60")], "")));
+        let manager = MemoryManager::new();
+        let cache = CacheBuilder::new(manager.clone())
+            .cacheable_methods(vec![http::Method::GET, http::Method::POST])
+            .build();
+        for body in &["q=1", "q=2", "q=1"] {
+            cache
+                .run_with(post("http://example.com/search", body), &origin)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(origin.hits(), 2);
+        assert_eq!(manager.list().await.unwrap().len(), 2);
+    }
+
+    #[async_attributes::test]
+    async fn cache_status_header_reports_hits_misses_and_revalidations() {
+        clock::mock::freeze();
+        let origin = MockOrigin::new(|req| {
+            if req.headers().contains_key(http::header::IF_NONE_MATCH) {
+                Ok(response(304, &[], ""))
+            } else {
+                Ok(response(
+                    200,
+                    &[("Cache-Control", "max-age=60"), ("ETag", "\"1\"")],
+                    "hello",
+                ))
+            }
+        });
+        let cache = CacheBuilder::new(MemoryManager::new())
+            .cache_status_header(true)
+            .build();
+        let key = "key=\"GET:http://example.com/\"";
+        let mut statuses = Vec::new();
+        for &elapsed in &[0, 10, 60] {
+            clock::mock::advance(Duration::from_secs(elapsed));
+            let res = cache
+                .run_with(get("http://example.com/"), &origin)
+                .await
+                .unwrap();
+            statuses.push(res.headers()["Cache-Status"].to_str().unwrap().to_string());
+        }
+
+        assert_eq!(
+            statuses,
+            vec![
+                format!("surf-cache; fwd=miss; fwd-status=200; {}", key),
+                format!("surf-cache; hit; ttl=50; {}", key),
+                format!("surf-cache; fwd=stale; fwd-status=304; {}", key),
+            ]
+        );
+    }
+
+    #[async_attributes::test]
+    async fn cache_status_header_gives_stale_hits_a_negative_ttl() {
+        clock::mock::freeze();
+        let origin =
+            MockOrigin::new(|_| Ok(response(200, &[("Cache-Control", "max-age=60")], "hello")));
+        let cache = CacheBuilder::new(MemoryManager::new())
+            .mode(CacheMode::ForceCache)
+            .cache_status_header(true)
+            .build();
+        cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+
+        clock::mock::advance(Duration::from_secs(75));
+        let res = cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(cache_status(&res), Some(CacheStatus::Stale));
+        assert_eq!(
+            res.headers()["Cache-Status"],
+            "surf-cache; hit; ttl=-15; key=\"GET:http://example.com/\""
+        );
+    }
+
+    #[async_attributes::test]
+    async fn bypassed_requests_never_touch_the_store() {
+        let origin =
+            MockOrigin::new(|_| Ok(response(200, &[("Cache-Control", "max-age=60")], "hello")));
+        let manager = MemoryManager::new();
+        let cache = CacheBuilder::new(manager.clone())
+            .bypass(|req| req.uri().path().starts_with("/auth/"))
+            .build();
+        for _ in 0..2 {
+            cache
+                .run_with(get("http://example.com/auth/token"), &origin)
+                .await
+                .unwrap();
+            cache
+                .run_with(get("http://example.com/public"), &origin)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(origin.hits(), 3);
+        assert!(!manager
+            .contains(&get("http://example.com/auth/token"))
+            .await
+            .unwrap());
+    }
+
+    #[async_attributes::test]
+    async fn only_if_cached_serves_stale_entries_and_504s_the_rest() {
+        let origin =
+            MockOrigin::new(|_req| Ok(response(200, &[("Cache-Control", "max-age=0")], "hello")));
+        let manager = MemoryManager::new();
+        let filler = Cache::new(CacheMode::Default, manager.clone());
+        filler
+            .run_with(get("http://example.com/stale"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(origin.hits(), 1);
+
+        let cache = Cache::new(CacheMode::OnlyIfCached, manager);
+        let res = cache
+            .run_with(get("http://example.com/stale"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(cache_status(&res), Some(CacheStatus::Stale));
+        let warning = res.headers()["warning"].to_str().unwrap();
+        assert!(warning.starts_with("112 "), "{}", warning);
+        assert_eq!(body_string(res).await, "hello");
+
+        let res = cache
+            .run_with(get("http://example.com/missing"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), http::StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(origin.hits(), 1);
+    }
+
+    #[async_attributes::test]
+    async fn event_stream_reports_a_miss_then_a_hit() {
+        use futures::stream::StreamExt;
+
+        let origin =
+            MockOrigin::new(|_req| Ok(response(200, &[("Cache-Control", "max-age=60")], "hello")));
+        let cache = Cache::new(CacheMode::Default, MemoryManager::new());
+        let mut events = cache.event_stream();
+        for _ in 0..2 {
+            cache
+                .run_with(get("http://example.com/"), &origin)
+                .await
+                .unwrap();
+        }
+
+        let miss = events.next().await.unwrap();
+        assert_eq!(miss.outcome, Outcome::Miss);
+        assert_eq!(miss.status, http::StatusCode::OK);
+        let hit = events.next().await.unwrap();
+        match hit.outcome {
+            Outcome::Hit(_) => {}
+            outcome => panic!("expected a hit, got {:?}", outcome),
+        }
+        assert_eq!(hit.status, http::StatusCode::OK);
+        assert_eq!(hit.key, miss.key);
+    }
+
+    #[async_attributes::test]
+    async fn max_served_age_revalidates_old_but_fresh_entries() {
+        clock::mock::freeze();
+        let origin = MockOrigin::new(|req| {
+            if req.headers().contains_key(http::header::IF_NONE_MATCH) {
+                Ok(response(304, &[("ETag", "\"1\"")], ""))
+            } else {
+                Ok(response(
+                    200,
+                    &[("ETag", "\"1\""), ("Cache-Control", "max-age=3600")],
+                    "hello",
+                ))
+            }
+        });
+        let cache = CacheBuilder::new(MemoryManager::new())
+            .max_served_age(Duration::from_secs(30))
+            .build();
+        cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+
+        clock::mock::advance(Duration::from_secs(10));
+        let res = cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(cache_status(&res), Some(CacheStatus::Fresh));
+        assert_eq!(origin.hits(), 1);
+
+        clock::mock::advance(Duration::from_secs(30));
+        let res = cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(cache_status(&res), Some(CacheStatus::Revalidated));
+        assert_eq!(body_string(res).await, "hello");
+        assert_eq!(origin.hits(), 2);
+        assert!(origin
+            .last_request()
+            .headers()
+            .contains_key(http::header::IF_NONE_MATCH));
+    }
+
+    #[async_attributes::test]
+    async fn binary_bodies_survive_a_304() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        let stored = bytes.clone();
+        let origin = MockOrigin::new(move |req| {
+            if req.headers().contains_key(http::header::IF_NONE_MATCH) {
+                Ok(response(304, &[("ETag", "\"1\"")], ""))
+            } else {
+                Ok(http::Response::builder()
+                    .header("ETag", "\"1\"")
+                    .header("Cache-Control", "no-cache")
+                    .header("Content-Type", "application/octet-stream")
+                    .body(Body::from(stored.clone()))
+                    .unwrap())
+            }
+        });
+        let cache = Cache::new(CacheMode::Default, MemoryManager::new());
+        cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+
+        let res = cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(cache_status(&res), Some(CacheStatus::Revalidated));
+        let mut body = Vec::new();
+        res.into_body().read_to_end(&mut body).await.unwrap();
+        assert_eq!(body, bytes);
+    }
+
+    #[async_attributes::test]
+    async fn stale_responses_served_on_error_are_marked_stale() {
+        let origin = MockOrigin::new(|req| {
+            if req.headers().contains_key(http::header::IF_NONE_MATCH) {
+                Err("origin unreachable".into())
+            } else {
+                Ok(response(
+                    200,
+                    &[("ETag", "\"1\""), ("Cache-Control", "max-age=0")],
+                    "stored",
+                ))
+            }
+        });
+        let cache = Cache::new(CacheMode::Default, MemoryManager::new());
+        cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+
+        let res = cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(
+            res.extensions().get::<CacheStatus>(),
+            Some(&CacheStatus::Stale)
+        );
+        assert_eq!(body_string(res).await, "stored");
+        assert_eq!(origin.hits(), 2);
+    }
+
+    #[async_attributes::test]
+    async fn should_cache_vetoes_only_the_responses_it_rejects() {
+        let origin = MockOrigin::new(|req| {
+            if req.uri().path() == "/secret" {
+                Ok(response(
+                    200,
+                    &[("Cache-Control", "max-age=60"), ("X-No-Cache-Me", "1")],
+                    "secret",
+                ))
+            } else {
+                Ok(response(200, &[("Cache-Control", "max-age=60")], "public"))
+            }
+        });
+        let manager = MemoryManager::new();
+        let cache = CacheBuilder::new(manager.clone())
+            .should_cache(|_, res| !res.headers().contains_key("X-No-Cache-Me"))
+            .build();
+
+        for uri in &["http://example.com/secret", "http://example.com/public"] {
+            for _ in 0..2 {
+                cache.run_with(get(uri), &origin).await.unwrap();
+            }
+        }
+        assert_eq!(origin.hits(), 3);
+        assert!(!manager
+            .contains(&get("http://example.com/secret"))
+            .await
+            .unwrap());
+        assert!(manager
+            .contains(&get("http://example.com/public"))
+            .await
+            .unwrap());
+    }
+
+    #[async_attributes::test]
+    async fn only_if_cached_requests_get_a_504_on_a_miss_in_default_mode() {
+        let origin =
+            MockOrigin::new(|_req| Ok(response(200, &[("Cache-Control", "max-age=60")], "hello")));
+        let cache = Cache::new(CacheMode::Default, MemoryManager::new());
+        let req = request(
+            http::Method::GET,
+            "http://example.com/",
+            &[("Cache-Control", "only-if-cached")],
+        );
+        let res = cache.run_with(req, &origin).await.unwrap();
+        assert_eq!(res.status(), http::StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(origin.hits(), 0);
+    }
+
+    #[async_attributes::test]
+    async fn chunked_responses_are_stored_without_transfer_encoding() {
+        let origin = MockOrigin::new(|_req| {
+            Ok(response(
+                200,
+                &[
+                    ("Cache-Control", "max-age=60"),
+                    ("Transfer-Encoding", "chunked"),
+                    ("Connection", "keep-alive"),
+                ],
+                "hello",
+            ))
+        });
+        let manager = MemoryManager::new();
+        let cache = Cache::new(CacheMode::Default, manager.clone());
+        cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+
+        let entry = manager
+            .get(&get("http://example.com/"))
+            .await
+            .unwrap()
+            .unwrap();
+        let headers = entry.response.headers();
+        assert!(!headers.contains_key(http::header::TRANSFER_ENCODING));
+        assert!(!headers.contains_key(http::header::CONNECTION));
+        assert_eq!(body_string(entry.response).await, "hello");
+    }
+
+    #[async_attributes::test]
+    async fn invalidated_urls_miss_on_the_next_request() {
+        let origin =
+            MockOrigin::new(|_req| Ok(response(200, &[("Cache-Control", "max-age=60")], "hello")));
+        let cache = Cache::new(CacheMode::Default, MemoryManager::new());
+        cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+
+        let url = surf::url::Url::parse("http://example.com/").unwrap();
+        cache.invalidate(http::Method::GET, url).await.unwrap();
+        let res = cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(cache_status(&res), Some(CacheStatus::Miss));
+        assert_eq!(origin.hits(), 2);
+    }
+
+    /// Keeps every log line written on the current thread, so tests can
+    /// check what was logged without seeing each other's lines.
+    struct CapturingLogger;
+
+    thread_local! {
+        static LOG_LINES: std::cell::RefCell<Vec<String>> = const { std::cell::RefCell::new(Vec::new()) };
+    }
+
+    impl log::Log for CapturingLogger {
+        fn enabled(&self, _metadata: &log::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record<'_>) {
+            LOG_LINES.with(|lines| lines.borrow_mut().push(record.args().to_string()));
+        }
+
+        fn flush(&self) {}
+    }
+
+    fn capture_logs() {
+        static LOGGER: CapturingLogger = CapturingLogger;
+        static INIT: std::sync::Once = std::sync::Once::new();
+        INIT.call_once(|| {
+            log::set_logger(&LOGGER).unwrap();
+            log::set_max_level(log::LevelFilter::Debug);
+        });
+    }
+
+    #[async_attributes::test]
+    async fn logs_one_summary_line_per_request() {
+        capture_logs();
+        clock::mock::freeze();
+        let origin =
+            MockOrigin::new(|_req| Ok(response(200, &[("Cache-Control", "max-age=60")], "hello")));
+        let cache = Cache::new(CacheMode::Default, MemoryManager::new());
+        cache
+            .run_with(get("http://example.com/logged"), &origin)
+            .await
+            .unwrap();
+        clock::mock::advance(Duration::from_secs(15));
+        cache
+            .run_with(get("http://example.com/logged"), &origin)
+            .await
+            .unwrap();
+        cache
+            .run_with(post("http://example.com/logged", "hi"), &origin)
+            .await
+            .unwrap();
+
+        let summaries: Vec<String> = LOG_LINES.with(|lines| {
+            lines
+                .borrow()
+                .iter()
+                .filter(|line| line.contains("example.com/logged") && line.contains("decision="))
+                .cloned()
+                .collect()
+        });
+        assert_eq!(summaries.len(), 3, "{:?}", summaries);
+        assert!(
+            summaries[0].contains("decision=miss age=0s ttl=-"),
+            "{}",
+            summaries[0]
+        );
+        assert!(
+            summaries[1].contains("decision=hit age=15s ttl=45s"),
+            "{}",
+            summaries[1]
+        );
+        assert!(
+            summaries[2].contains("decision=bypass age=- ttl=-"),
+            "{}",
+            summaries[2]
+        );
+    }
+
+    #[async_attributes::test]
+    async fn cache_heuristic_scales_the_heuristic_lifetime() {
+        clock::mock::freeze();
+        let now = clock::now();
+        let date = httpdate::fmt_http_date(now);
+        let last_modified = httpdate::fmt_http_date(now - Duration::from_secs(100));
+        let origin = MockOrigin::new(move |_req| {
+            Ok(response(
+                200,
+                &[("Date", &date), ("Last-Modified", &last_modified)],
+                "hello",
+            ))
+        });
+        let default = Cache::new(CacheMode::Default, MemoryManager::new());
+        let generous = CacheBuilder::new(MemoryManager::new())
+            .cache_heuristic(0.5)
+            .build();
+        for cache in &[&default, &generous] {
+            cache
+                .run_with(get("http://example.com/"), &origin)
+                .await
+                .unwrap();
+        }
+
+        // A tenth of 100s has passed, but not half of it.
+        clock::mock::advance(Duration::from_secs(20));
+        let res = generous
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(cache_status(&res), Some(CacheStatus::Fresh));
+        assert_eq!(origin.hits(), 2);
+        let res = default
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        assert_ne!(cache_status(&res), Some(CacheStatus::Fresh));
+        assert_eq!(origin.hits(), 3);
+    }
+
+    #[async_attributes::test]
+    async fn set_cookie_is_never_replayed_from_the_cache() {
+        let origin = MockOrigin::new(|_req| {
+            Ok(response(
+                200,
+                &[
+                    ("Cache-Control", "max-age=60"),
+                    ("Set-Cookie", "session=alice"),
+                ],
+                "hello",
+            ))
+        });
+        let cache = Cache::new(CacheMode::Default, MemoryManager::new());
+        let res = cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(res.headers()["Set-Cookie"], "session=alice");
+
+        let res = cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(cache_status(&res), Some(CacheStatus::Fresh));
+        assert!(!res.headers().contains_key(http::header::SET_COOKIE));
+    }
+
+    #[async_attributes::test]
+    async fn stale_if_error_serves_carry_a_110_warning() {
+        let origin = MockOrigin::new(|req| {
+            if req.headers().contains_key(http::header::IF_NONE_MATCH) {
+                Ok(response(503, &[], ""))
+            } else {
+                Ok(response(
+                    200,
+                    &[
+                        ("ETag", "\"1\""),
+                        ("Cache-Control", "max-age=0, stale-if-error=60"),
+                    ],
+                    "stored",
+                ))
+            }
+        });
+        let cache = Cache::new(CacheMode::Default, MemoryManager::new());
+        cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+
+        let res = cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(cache_status(&res), Some(CacheStatus::Stale));
+        let codes: Vec<_> = res
+            .headers()
+            .get_all("warning")
+            .iter()
+            .map(|warning| warning.to_str().unwrap()[..3].to_string())
+            .collect();
+        assert!(codes.contains(&"110".to_string()), "{:?}", codes);
+        assert_eq!(body_string(res).await, "stored");
+    }
+
+    /// Everything, backoff timer included, runs on a bare executor with no
+    /// async-std (or tokio) runtime behind it.
+    #[test]
+    fn revalidates_stale_while_revalidate_entries_without_a_runtime() {
+        let failures = AtomicUsize::new(0);
+        let origin = MockOrigin::new(|req| {
+            if !req.headers().contains_key(http::header::IF_NONE_MATCH) {
+                Ok(response(
+                    200,
+                    &[
+                        ("Cache-Control", "max-age=0, stale-while-revalidate=60"),
+                        ("ETag", "\"1\""),
+                    ],
+                    "hello",
+                ))
+            } else if failures.fetch_add(1, Ordering::SeqCst) == 0 {
+                Err("connection reset".into())
+            } else {
+                Ok(response(304, &[], ""))
+            }
+        });
+        let cache = CacheBuilder::new(MemoryManager::new())
+            .revalidation_retries(1, Duration::from_millis(1))
+            .build();
+        futures::executor::block_on(async {
+            cache
+                .run_with(get("http://example.com/"), &origin)
+                .await
+                .unwrap();
+
+            let res = cache
+                .run_with(get("http://example.com/"), &origin)
+                .await
+                .unwrap();
+            assert_eq!(cache_status(&res), Some(CacheStatus::Revalidated));
+            assert_eq!(body_string(res).await, "hello");
+        });
+        assert_eq!(origin.hits(), 3);
+    }
+
+    #[async_attributes::test]
+    async fn forced_revalidations_of_fresh_entries_fall_back_on_a_503() {
+        let origin = MockOrigin::new(|req| {
+            if req.headers().contains_key(http::header::IF_NONE_MATCH) {
+                Ok(response(503, &[], "unavailable"))
+            } else {
+                Ok(response(
+                    200,
+                    &[("ETag", "\"1\""), ("Cache-Control", "max-age=60")],
+                    "stored",
+                ))
+            }
+        });
+        let cache = Cache::new(CacheMode::Default, MemoryManager::new());
+        cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+
+        let req = request(
+            http::Method::GET,
+            "http://example.com/",
+            &[("Cache-Control", "no-cache")],
+        );
+        let res = cache.run_with(req, &origin).await.unwrap();
+        assert_eq!(origin.hits(), 2);
+        assert_eq!(res.status(), http::StatusCode::OK);
+        let warning = res.headers()["warning"].to_str().unwrap();
+        assert!(warning.starts_with("111 "), "{}", warning);
+        assert_eq!(body_string(res).await, "stored");
+    }
+
+    #[async_attributes::test]
+    async fn partitions_keep_the_same_url_apart() {
+        let origin = MockOrigin::new(|req| {
+            let site = req.headers()["X-Top-Level-Site"]
+                .to_str()
+                .unwrap()
+                .to_string();
+            Ok(response(200, &[("Cache-Control", "max-age=60")], &site))
+        });
+        let cache = CacheBuilder::new(MemoryManager::new())
+            .partition(|req| {
+                req.headers()
+                    .get("X-Top-Level-Site")
+                    .and_then(|site| site.to_str().ok())
+                    .map(String::from)
+            })
+            .build();
+        let from = |site| {
+            request(
+                http::Method::GET,
+                "http://example.com/shared",
+                &[("X-Top-Level-Site", site)],
+            )
+        };
+
+        for site in &["a.com", "b.com"] {
+            let res = cache.run_with(from(site), &origin).await.unwrap();
+            assert_eq!(cache_status(&res), Some(CacheStatus::Miss));
+        }
+        for site in &["a.com", "b.com"] {
+            let res = cache.run_with(from(site), &origin).await.unwrap();
+            assert_eq!(cache_status(&res), Some(CacheStatus::Fresh));
+            assert_eq!(body_string(res).await, *site);
+        }
+        assert_eq!(origin.hits(), 2);
+    }
+
+    #[async_attributes::test]
+    async fn with_mode_and_with_manager_change_how_requests_are_answered() {
+        let origin =
+            MockOrigin::new(|_req| Ok(response(200, &[("Cache-Control", "max-age=60")], "hello")));
+        let cache = Cache::new(CacheMode::Default, MemoryManager::new());
+        cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        let res = cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(cache_status(&res), Some(CacheStatus::Fresh));
+        assert_eq!(origin.hits(), 1);
+
+        // Same store, but every request goes to the origin.
+        let cache = cache.with_mode(CacheMode::NoStore);
+        let res = cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(cache_status(&res), Some(CacheStatus::Miss));
+        assert_eq!(origin.hits(), 2);
+
+        // An empty store, which can't answer anything offline.
+        let cache = cache
+            .with_manager(MemoryManager::new())
+            .with_mode(CacheMode::OnlyIfCached);
+        let res = cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), http::StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(origin.hits(), 2);
+    }
+
+    const LAST_MODIFIED: &str = "Tue, 15 Nov 1994 12:45:26 GMT";
+
+    /// Stores a response with both validators, then revalidates it against
+    /// an origin that answers with a 304 carrying `not_modified_headers`.
+    async fn revalidate_with_both_validators(not_modified_headers: &'static [(&str, &str)]) {
+        let origin = MockOrigin::new(move |req| {
+            if req.headers().contains_key(http::header::IF_NONE_MATCH) {
+                Ok(response(304, not_modified_headers, ""))
+            } else {
+                Ok(response(
+                    200,
+                    &[
+                        ("ETag", "\"1\""),
+                        ("Last-Modified", LAST_MODIFIED),
+                        ("Cache-Control", "no-cache"),
+                    ],
+                    "stored",
+                ))
+            }
+        });
+        let cache = Cache::new(CacheMode::Default, MemoryManager::new());
+        cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+
+        let res = cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        let sent = origin.last_request();
+        assert_eq!(sent.headers()["If-None-Match"], "\"1\"");
+        assert_eq!(sent.headers()["If-Modified-Since"], LAST_MODIFIED);
+        assert_eq!(cache_status(&res), Some(CacheStatus::Revalidated));
+        assert_eq!(res.headers()["ETag"], "\"1\"");
+        assert_eq!(res.headers()["Last-Modified"], LAST_MODIFIED);
+        assert_eq!(body_string(res).await, "stored");
+    }
+
+    #[async_attributes::test]
+    async fn revalidates_when_the_origin_matched_the_etag() {
+        revalidate_with_both_validators(&[("ETag", "\"1\"")]).await;
+    }
+
+    #[async_attributes::test]
+    async fn revalidates_when_the_origin_matched_the_date() {
+        revalidate_with_both_validators(&[("Last-Modified", LAST_MODIFIED)]).await;
+    }
+
+    #[async_attributes::test]
+    async fn ignore_vary_serves_across_accept_headers() {
+        for &ignore_vary in &[false, true] {
+            let origin = MockOrigin::new(|_req| {
+                Ok(response(
+                    200,
+                    &[("Cache-Control", "max-age=60"), ("Vary", "Accept")],
+                    "hello",
+                ))
+            });
+            let cache = CacheBuilder::new(MemoryManager::new())
+                .ignore_vary(ignore_vary)
+                .build();
+            for accept in &["text/html", "application/json"] {
+                let req = request(
+                    http::Method::GET,
+                    "http://example.com/",
+                    &[("Accept", accept)],
+                );
+                cache.run_with(req, &origin).await.unwrap();
+            }
+            let expected = if ignore_vary { 1 } else { 2 };
+            assert_eq!(origin.hits(), expected, "ignore_vary: {}", ignore_vary);
+        }
+    }
+
+    #[async_attributes::test]
+    async fn answers_matching_conditional_requests_with_a_local_304() {
+        let origin = MockOrigin::new(|_req| {
+            Ok(response(
+                200,
+                &[("ETag", "\"1\""), ("Cache-Control", "max-age=60")],
+                "hello",
+            ))
+        });
+        let cache = Cache::new(CacheMode::Default, MemoryManager::new());
+        cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+
+        let req = request(
+            http::Method::GET,
+            "http://example.com/",
+            &[("If-None-Match", "\"1\"")],
+        );
+        let res = cache.run_with(req, &origin).await.unwrap();
+        assert_eq!(res.status(), http::StatusCode::NOT_MODIFIED);
+        assert_eq!(res.headers()["ETag"], "\"1\"");
+        assert_eq!(body_string(res).await, "");
+        assert_eq!(origin.hits(), 1);
+
+        let req = request(
+            http::Method::GET,
+            "http://example.com/",
+            &[("If-None-Match", "\"2\"")],
+        );
+        let res = cache.run_with(req, &origin).await.unwrap();
+        assert_eq!(res.status(), http::StatusCode::OK);
+        assert_eq!(body_string(res).await, "hello");
+        assert_eq!(origin.hits(), 1);
+    }
+
+    #[async_attributes::test]
+    async fn max_age_zero_responses_are_revalidated_on_the_next_request() {
+        let origin = MockOrigin::new(|req| {
+            if req.headers().contains_key(http::header::IF_NONE_MATCH) {
+                Ok(response(304, &[("ETag", "\"1\"")], ""))
+            } else {
+                Ok(response(
+                    200,
+                    &[("ETag", "\"1\""), ("Cache-Control", "max-age=0")],
+                    "hello",
+                ))
+            }
+        });
+        let cache = Cache::new(CacheMode::Default, MemoryManager::new());
+        cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+
+        let res = cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(cache_status(&res), Some(CacheStatus::Revalidated));
+        assert_eq!(origin.hits(), 2);
+        assert_eq!(origin.last_request().headers()["If-None-Match"], "\"1\"");
+        assert_eq!(body_string(res).await, "hello");
+    }
+
+    #[test]
+    fn cache_modes_copy_into_request_extensions() {
+        let mode = CacheMode::ForceCache;
+        let mut req = get("http://example.com/");
+        req.extensions_mut().insert(mode);
+        // `mode` is still usable: it was copied, not moved.
+        assert_eq!(req.extensions().get::<CacheMode>(), Some(&mode));
+
+        let modes: std::collections::HashSet<CacheMode> =
+            vec![mode, CacheMode::ForceCache].into_iter().collect();
+        assert_eq!(modes.len(), 1);
+    }
+
+    #[async_attributes::test]
+    async fn served_responses_carry_their_policy() {
+        let origin =
+            MockOrigin::new(|_req| Ok(response(200, &[("Cache-Control", "max-age=60")], "hello")));
+        let cache = Cache::new(CacheMode::Default, MemoryManager::new());
+        cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+
+        let res = cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(cache_status(&res), Some(CacheStatus::Fresh));
+        let policy = res.extensions().get::<CachePolicy>().unwrap();
+        assert_eq!(policy.max_age(), Duration::from_secs(60));
+        assert!(policy.is_storable());
+    }
+
+    #[async_attributes::test]
+    async fn on_serve_only_touches_responses_served_from_the_store() {
+        let origin =
+            MockOrigin::new(|_req| Ok(response(200, &[("Cache-Control", "max-age=60")], "hello")));
+        let manager = MemoryManager::new();
+        let cache = CacheBuilder::new(manager.clone())
+            .on_serve(|res| {
+                res.headers_mut()
+                    .insert("Served-From-Cache", http::HeaderValue::from_static("1"));
+            })
+            .build();
+        let res = cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        assert!(!res.headers().contains_key("Served-From-Cache"));
+
+        let res = cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(res.headers()["Served-From-Cache"], "1");
+
+        let entry = manager
+            .get(&get("http://example.com/"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!entry.response.headers().contains_key("Served-From-Cache"));
+    }
+
+    #[async_attributes::test]
+    async fn pragma_no_cache_requests_revalidate_fresh_entries() {
+        let origin = MockOrigin::new(|req| {
+            if req.headers().contains_key(http::header::IF_NONE_MATCH) {
+                Ok(response(304, &[("ETag", "\"1\"")], ""))
+            } else {
+                Ok(response(
+                    200,
+                    &[("ETag", "\"1\""), ("Cache-Control", "max-age=60")],
+                    "hello",
+                ))
+            }
+        });
+        let cache = Cache::new(CacheMode::Default, MemoryManager::new());
+        cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+
+        let req = request(
+            http::Method::GET,
+            "http://example.com/",
+            &[("Pragma", "no-cache")],
+        );
+        let res = cache.run_with(req, &origin).await.unwrap();
+        assert_eq!(cache_status(&res), Some(CacheStatus::Revalidated));
+        assert_eq!(origin.hits(), 2);
+        assert_eq!(body_string(res).await, "hello");
+    }
+
+    #[async_attributes::test]
+    async fn offline_caches_serve_hits_and_explain_misses() {
+        let origin =
+            MockOrigin::new(|_req| Ok(response(200, &[("Cache-Control", "max-age=60")], "hello")));
+        let manager = MemoryManager::new();
+        Cache::new(CacheMode::Default, manager.clone())
+            .run_with(get("http://example.com/warm"), &origin)
+            .await
+            .unwrap();
+        let offline = CacheBuilder::new(manager)
+            .mode(CacheMode::OnlyIfCached)
+            .build();
+
+        let res = offline
+            .run_with(get("http://example.com/warm"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(cache_status(&res), Some(CacheStatus::Fresh));
+        assert_eq!(body_string(res).await, "hello");
+
+        let res = offline
+            .run_with(get("http://example.com/cold"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), http::StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(
+            body_string(res).await,
+            "GET http://example.com/cold is not in the cache, and only cached responses may be used"
+        );
+        assert_eq!(origin.hits(), 1);
+    }
+
+    #[async_attributes::test]
+    async fn time_to_live_counts_down_from_max_age() {
+        clock::mock::freeze();
+        let origin =
+            MockOrigin::new(|_req| Ok(response(200, &[("Cache-Control", "max-age=60")], "hello")));
+        let cache = Cache::new(CacheMode::Default, MemoryManager::new());
+        let url = || surf::url::Url::parse("http://example.com/").unwrap();
+        assert_eq!(
+            cache.time_to_live(http::Method::GET, url()).await.unwrap(),
+            None
+        );
+        cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+
+        for &(elapsed, ttl) in &[(0, 60), (20, 40), (40, 0), (30, 0)] {
+            clock::mock::advance(Duration::from_secs(elapsed));
+            assert_eq!(
+                cache.time_to_live(http::Method::GET, url()).await.unwrap(),
+                Some(Duration::from_secs(ttl))
+            );
+        }
+    }
+
+    #[async_attributes::test]
+    async fn shared_caches_strip_headers_named_private() {
+        let origin = MockOrigin::new(|_req| {
+            Ok(response(
+                200,
+                &[
+                    ("Cache-Control", "max-age=60, private=\"Set-Cookie\""),
+                    ("Set-Cookie", "session=alice"),
+                    ("X-Kept", "1"),
+                ],
+                "hello",
+            ))
+        });
+        // Nothing is stripped unconditionally, so only `private` can drop
+        // the cookie.
+        let cache = CacheBuilder::new(MemoryManager::new())
+            .shared(true)
+            .strip_headers(Vec::new())
+            .build();
+        cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+
+        let res = cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+        assert_eq!(cache_status(&res), Some(CacheStatus::Fresh));
+        assert!(!res.headers().contains_key(http::header::SET_COOKIE));
+        assert_eq!(res.headers()["X-Kept"], "1");
+        assert_eq!(body_string(res).await, "hello");
+        assert_eq!(origin.hits(), 1);
+    }
+
+    #[test]
+    fn weak_comparison_ignores_the_weak_marker() {
+        assert!(weak_etag_match("\"x\"", "\"x\""));
+        assert!(weak_etag_match("W/\"x\"", "\"x\""));
+        assert!(weak_etag_match("\"x\"", "W/\"x\""));
+        assert!(weak_etag_match("W/\"x\"", "W/\"x\""));
+        assert!(!weak_etag_match("W/\"x\"", "\"y\""));
+        assert!(!weak_etag_match("\"x\"", "\"X\""));
+    }
+
+    #[async_attributes::test]
+    async fn local_304s_match_weak_and_strong_etags_alike() {
+        let origin = MockOrigin::new(|_req| {
+            Ok(response(
+                200,
+                &[("ETag", "W/\"x\""), ("Cache-Control", "max-age=60")],
+                "hello",
+            ))
+        });
+        let cache = Cache::new(CacheMode::Default, MemoryManager::new());
+        cache
+            .run_with(get("http://example.com/"), &origin)
+            .await
+            .unwrap();
+
+        for &(if_none_match, status) in &[
+            ("\"x\"", http::StatusCode::NOT_MODIFIED),
+            ("W/\"x\"", http::StatusCode::NOT_MODIFIED),
+            ("\"y\", W/\"x\"", http::StatusCode::NOT_MODIFIED),
+            ("W/\"y\"", http::StatusCode::OK),
+        ] {
+            let req = request(
+                http::Method::GET,
+                "http://example.com/",
+                &[("If-None-Match", if_none_match)],
+            );
+            let res = cache.run_with(req, &origin).await.unwrap();
+            assert_eq!(res.status(), status, "If-None-Match: {}", if_none_match);
+        }
+        assert_eq!(origin.hits(), 1);
+    }
+
+    #[async_attributes::test]
+    async fn only_if_cached_never_sends_uncacheable_requests_upstream() {
+        let origin =
+            MockOrigin::new(|_req| Ok(response(200, &[("Cache-Control", "max-age=60")], "hello")));
+        let cache = CacheBuilder::new(MemoryManager::new())
+            .mode(CacheMode::OnlyIfCached)
+            .build();
+        let ranged = request(
+            http::Method::GET,
+            "http://example.com/",
+            &[("Range", "bytes=0-1")],
+        );
+        let res = cache.run_with(ranged, &origin).await.unwrap();
+        assert_eq!(res.status(), http::StatusCode::GATEWAY_TIMEOUT);
+
+        // The request directive does the same in any mode.
+        let cache = Cache::new(CacheMode::Default, MemoryManager::new());
+        let post = request(
+            http::Method::POST,
+            "http://example.com/",
+            &[("Cache-Control", "only-if-cached")],
+        );
+        let res = cache.run_with(post, &origin).await.unwrap();
+        assert_eq!(res.status(), http::StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(origin.hits(), 0);
+    }
+
+    /// A manager that can look up policies, but can't read entries back.
+    struct MetadataOnlyManager(MemoryManager);
+
+    #[async_trait]
+    impl CacheManager for MetadataOnlyManager {
+        async fn get(&self, _req: &Request) -> Result<Option<CacheEntry>, surf::Exception> {
+            Err("bodies aren't readable".into())
+        }
+
+        async fn get_metadata(
+            &self,
+            req: &Request,
+        ) -> Result<Option<CachePolicy>, surf::Exception> {
+            self.0.get_metadata(req).await
+        }
+
+        async fn put(
+            &self,
+            req: &Request,
+            res: Response,
+            policy: CachePolicy,
+        ) -> Result<Response, surf::Exception> {
+            self.0.put(req, res, policy).await
+        }
+
+        async fn delete(&self, req: &Request) -> Result<(), surf::Exception> {
+            self.0.delete(req).await
+        }
+    }
+
+    #[async_attributes::test]
+    async fn invalidate_finds_encodings_without_reading_bodies() {
+        let origin = encoded_origin();
+        let manager = MemoryManager::new();
+        let filler = Cache::new(CacheMode::Default, manager.clone());
+        for encoding in &["gzip", "identity"] {
+            let req = request(
+                http::Method::GET,
+                "http://example.com/",
+                &[("Accept-Encoding", encoding)],
+            );
+            filler.run_with(req, &origin).await.unwrap();
+        }
+        assert_eq!(manager.list().await.unwrap().len(), 3);
+
+        let cache = Cache::new(CacheMode::Default, MetadataOnlyManager(manager.clone()));
+        let url = surf::url::Url::parse("http://example.com/").unwrap();
+        cache.invalidate(http::Method::GET, url).await.unwrap();
+        assert!(manager.list().await.unwrap().is_empty());
+    }
+}
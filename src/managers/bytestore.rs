@@ -0,0 +1,207 @@
+use async_trait::async_trait;
+use surf::middleware::{Request, Response};
+
+use super::bounded_req_key;
+use super::store::{from_store, to_store, Store};
+use crate::{CacheEntry, CacheManager, CachePolicy, EntryStats};
+
+/// A plain key-value store for raw bytes. Implementing this, and wrapping it
+/// in a [`ByteStoreManager`], is all it takes to back the cache with a new
+/// kind of storage: serializing entries is taken care of.
+#[async_trait]
+pub trait ByteStore {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, surf::Exception>;
+    /// Replaces the value under `key`. This must be atomic: a concurrent
+    /// `get` has to see either the old value or the new one in full.
+    async fn put(&self, key: &str, value: Vec<u8>) -> Result<(), surf::Exception>;
+    async fn delete(&self, key: &str) -> Result<(), surf::Exception>;
+}
+
+/// Implements [`CacheManager`] on top of any [`ByteStore`], storing each
+/// entry under a single key in the same format
+/// [`CACacheManager`](crate::CACacheManager) uses. Since the whole entry is
+/// one value, entries are exactly as atomic as the store's `put`.
+#[derive(Debug, Clone)]
+pub struct ByteStoreManager<S: ByteStore> {
+    pub store: S,
+    /// Prepended to every key, so several managers can share one store
+    /// without their entries colliding.
+    pub namespace: Option<String>,
+    /// Keys longer than this are replaced by a hash of themselves, for
+    /// stores that limit key length.
+    pub max_key_len: Option<usize>,
+}
+
+impl<S: ByteStore> ByteStoreManager<S> {
+    pub fn new(store: S) -> Self {
+        ByteStoreManager {
+            store,
+            namespace: None,
+            max_key_len: None,
+        }
+    }
+
+    /// Prefixes every key this manager uses with `namespace`.
+    pub fn with_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = Some(namespace.into());
+        self
+    }
+
+    /// Hashes any key longer than `max_key_len`.
+    pub fn with_max_key_len(mut self, max_key_len: usize) -> Self {
+        self.max_key_len = Some(max_key_len);
+        self
+    }
+
+    fn key(&self, req: &Request) -> String {
+        bounded_req_key(self.namespace.as_deref(), req, self.max_key_len)
+    }
+}
+
+#[async_trait]
+impl<S: ByteStore + Send + Sync> CacheManager for ByteStoreManager<S> {
+    async fn get(&self, req: &Request) -> Result<Option<CacheEntry>, surf::Exception> {
+        let key = self.key(req);
+        match self.store.get(&key).await? {
+            Some(bytes) => {
+                let store = match Store::from_bytes(&bytes)? {
+                    Some(store) => store,
+                    None => {
+                        log::debug!("removing cache entry {} from an older version", key);
+                        self.store.delete(&key).await?;
+                        return Ok(None);
+                    }
+                };
+                if !store.is_intact() {
+                    log::warn!("removing corrupted cache entry {}", key);
+                    self.store.delete(&key).await?;
+                    return Ok(None);
+                }
+                Ok(Some(CacheEntry {
+                    response: from_store(&store)?,
+                    policy: store.policy,
+                    stored_at: store.stored_at,
+                    namespace: self.namespace.clone(),
+                }))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn put(
+        &self,
+        req: &Request,
+        res: Response,
+        policy: CachePolicy,
+    ) -> Result<Response, surf::Exception> {
+        let store = to_store(res, policy).await?;
+        self.store.put(&self.key(req), store.to_bytes()?).await?;
+        from_store(&store)
+    }
+
+    async fn delete(&self, req: &Request) -> Result<(), surf::Exception> {
+        self.store.delete(&self.key(req)).await
+    }
+
+    async fn stats(&self, req: &Request) -> Result<Option<EntryStats>, surf::Exception> {
+        match self.store.get(&self.key(req)).await? {
+            Some(bytes) => Ok(Store::from_bytes(&bytes)?.map(|store| store.stats())),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use futures::io::AsyncReadExt;
+    use surf::middleware::Body;
+
+    use super::*;
+
+    /// The simplest store there is.
+    #[derive(Debug, Clone, Default)]
+    struct MapStore(Arc<Mutex<HashMap<String, Vec<u8>>>>);
+
+    #[async_trait]
+    impl ByteStore for MapStore {
+        async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, surf::Exception> {
+            Ok(self.0.lock().unwrap().get(key).cloned())
+        }
+
+        async fn put(&self, key: &str, value: Vec<u8>) -> Result<(), surf::Exception> {
+            self.0.lock().unwrap().insert(key.to_string(), value);
+            Ok(())
+        }
+
+        async fn delete(&self, key: &str) -> Result<(), surf::Exception> {
+            self.0.lock().unwrap().remove(key);
+            Ok(())
+        }
+    }
+
+    fn get(uri: &str) -> Request {
+        http::Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    fn response(body: &str) -> Response {
+        http::Response::builder()
+            .header("Cache-Control", "max-age=60")
+            .header("Content-Type", "text/plain")
+            .body(Body::from(body.as_bytes().to_vec()))
+            .unwrap()
+    }
+
+    async fn body_string(res: Response) -> String {
+        let mut body = String::new();
+        res.into_body().read_to_string(&mut body).await.unwrap();
+        body
+    }
+
+    #[async_attributes::test]
+    async fn round_trips_entries_through_a_plain_map() {
+        let store = MapStore::default();
+        let manager = ByteStoreManager::new(store.clone());
+        let req = get("http://example.com/1");
+        let policy = CachePolicy::new(&req, &response(""));
+        manager.put(&req, response("one"), policy).await.unwrap();
+        assert_eq!(store.0.lock().unwrap().len(), 1);
+
+        let entry = manager.get(&req).await.unwrap().unwrap();
+        assert_eq!(entry.response.headers()["content-type"], "text/plain");
+        assert_eq!(body_string(entry.response).await, "one");
+        assert!(manager
+            .get(&get("http://example.com/2"))
+            .await
+            .unwrap()
+            .is_none());
+
+        manager.delete(&req).await.unwrap();
+        assert!(manager.get(&req).await.unwrap().is_none());
+        assert!(store.0.lock().unwrap().is_empty());
+    }
+
+    #[async_attributes::test]
+    async fn hashes_keys_past_max_key_len() {
+        let store = MapStore::default();
+        let manager = ByteStoreManager::new(store.clone()).with_max_key_len(100);
+        let short = get("http://example.com/short");
+        let long = get(&format!("http://example.com/?q={}", "x".repeat(500)));
+        for req in &[&short, &long] {
+            let policy = CachePolicy::new(req, &response(""));
+            manager.put(req, response("hi"), policy).await.unwrap();
+        }
+
+        let mut keys: Vec<String> = store.0.lock().unwrap().keys().cloned().collect();
+        keys.sort();
+        assert_eq!(keys[0], "GET:http://example.com/short");
+        assert!(keys[1].starts_with("sha256:"), "{}", keys[1]);
+        assert_eq!(keys[1].len(), "sha256:".len() + 64);
+        assert_eq!(
+            body_string(manager.get(&long).await.unwrap().unwrap().response).await,
+            "hi"
+        );
+    }
+}
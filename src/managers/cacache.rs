@@ -0,0 +1,989 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use futures::future;
+use futures::io::AsyncWriteExt;
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use surf::middleware::{Request, Response};
+
+use super::bounded_req_key;
+use super::store::{from_store, to_store, Store, SCHEMA_VERSION};
+use crate::{clock, CacheEntry, CacheManager, CachePolicy, EntryInfo, EntryStats};
+
+/// Implements [`CacheManager`] with [`cacache`](https://github.com/zkat/cacache-rs) as the backend.
+///
+/// Bodies are content-addressed and only become visible once fully written,
+/// so a `get` racing a `put` for the same request sees either the old entry
+/// or the new one. The policy sidecar is written after the entry and is
+/// only trusted when it's at least as new, so it never pairs a policy with
+/// the wrong response.
+///
+/// When `max_entries` or `max_total_bytes` is set, the manager reads
+/// cacache's index once, on the first `put`, and from then on keeps track in
+/// memory of each entry's size and when it was last stored or read, so
+/// enforcing the limits never means rescanning the index. Clones share that
+/// view. Entries other processes store or read in the same directory aren't
+/// seen until the next manager is created.
+#[derive(Debug, Clone)]
+pub struct CACacheManager {
+    /// Directory the cache is stored in.
+    pub path: String,
+    /// Prepended to every key, so several managers can share one directory
+    /// without their entries colliding.
+    pub namespace: Option<String>,
+    /// Maximum number of entries to keep. When a `put` goes over, the least
+    /// recently used entries are evicted.
+    pub max_entries: Option<usize>,
+    /// Keys longer than this are replaced by a hash of themselves.
+    pub max_key_len: Option<usize>,
+    /// Maximum combined size, in bytes, of the stored entries and their
+    /// policy sidecars, as recorded in cacache's index. When a `put` goes
+    /// over, the least recently used entries are evicted until the cache
+    /// fits again.
+    pub max_total_bytes: Option<u64>,
+    usage: Arc<Mutex<Option<Usage>>>,
+}
+
+/// What's stored, as far as eviction is concerned.
+#[derive(Debug, Default)]
+struct Usage {
+    /// When each entry was last stored or read, in milliseconds since the
+    /// epoch as in cacache's index, and how many bytes it takes up along
+    /// with its policy sidecar.
+    entries: HashMap<String, (u128, u64)>,
+    /// The same entries, least recently used first.
+    by_use: BTreeSet<(u128, String)>,
+    total_bytes: u64,
+}
+
+impl Usage {
+    /// Records a use of `key` at `time`, and, if it was just written, the
+    /// `size` it now takes up.
+    fn record(&mut self, key: &str, time: u128, size: Option<u64>) {
+        let (last_used, old_size) = self.entries.remove(key).unwrap_or((0, 0));
+        self.by_use.remove(&(last_used, key.to_string()));
+        let time = time.max(last_used);
+        let size = size.unwrap_or(old_size);
+        self.entries.insert(key.to_string(), (time, size));
+        self.by_use.insert((time, key.to_string()));
+        self.total_bytes = self.total_bytes - old_size + size;
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some((last_used, size)) = self.entries.remove(key) {
+            self.by_use.remove(&(last_used, key.to_string()));
+            self.total_bytes -= size;
+        }
+    }
+
+    fn least_recently_used(&self) -> Option<String> {
+        self.by_use.iter().next().map(|(_, key)| key.clone())
+    }
+}
+
+fn now_millis() -> u128 {
+    clock::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+}
+
+impl Default for CACacheManager {
+    fn default() -> Self {
+        CACacheManager::new("./surf-cacache")
+    }
+}
+
+const POLICY_PREFIX: &str = "policy:";
+
+fn policy_key(key: &str) -> String {
+    format!("{}{}", POLICY_PREFIX, key)
+}
+
+/// What's stored in a policy sidecar. Versioned along with `Store`, whose
+/// policy it copies.
+#[derive(Deserialize, Serialize)]
+struct Sidecar<P> {
+    /// Always `SCHEMA_VERSION` when written. Sidecars from before
+    /// versioning was added read as `0`.
+    #[serde(default)]
+    schema: u32,
+    policy: P,
+}
+
+impl CACacheManager {
+    /// Creates a manager that stores its cache under `path`.
+    pub fn new(path: impl Into<String>) -> Self {
+        CACacheManager {
+            path: path.into(),
+            namespace: None,
+            max_entries: None,
+            max_key_len: None,
+            max_total_bytes: None,
+            usage: Default::default(),
+        }
+    }
+
+    /// Caps the number of stored entries at `max_entries`.
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    /// Caps the combined size of the stored entries at `max_total_bytes`.
+    pub fn with_max_total_bytes(mut self, max_total_bytes: u64) -> Self {
+        self.max_total_bytes = Some(max_total_bytes);
+        self
+    }
+
+    /// Prefixes every key this manager uses with `namespace`.
+    pub fn with_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = Some(namespace.into());
+        self
+    }
+
+    /// Hashes any key longer than `max_key_len`, e.g. for requests to very
+    /// long URLs.
+    pub fn with_max_key_len(mut self, max_key_len: usize) -> Self {
+        self.max_key_len = Some(max_key_len);
+        self
+    }
+
+    pub(crate) fn key(&self, req: &Request) -> String {
+        bounded_req_key(self.namespace.as_deref(), req, self.max_key_len)
+    }
+
+    async fn read_policy(&self, key: &str) -> Result<Option<CachePolicy>, surf::Exception> {
+        let bytes = match cacache::read(&self.path, &policy_key(key)).await {
+            Ok(bytes) => bytes,
+            Err(_e) => return Ok(None),
+        };
+        // Only the version is looked at first, so a sidecar whose layout
+        // can't be read anymore is a miss rather than an error.
+        match serde_json::from_slice::<Sidecar<serde::de::IgnoredAny>>(&bytes) {
+            Ok(sidecar) if sidecar.schema == SCHEMA_VERSION => {
+                let sidecar: Sidecar<CachePolicy> = serde_json::from_slice(&bytes)?;
+                Ok(Some(sidecar.policy))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn in_namespace(&self, key: &str) -> bool {
+        match &self.namespace {
+            Some(namespace) => key.starts_with(&format!("{}:", namespace)),
+            None => true,
+        }
+    }
+
+    /// Index entries for everything stored in this manager's namespace, not
+    /// counting the policy sidecars.
+    fn index(&self) -> Result<Vec<cacache::Metadata>, surf::Exception> {
+        let mut entries = Vec::new();
+        for md in cacache::list_sync(&self.path) {
+            let md = md?;
+            if self.in_namespace(&md.key) && !md.key.starts_with(POLICY_PREFIX) {
+                entries.push(md);
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Reads cacache's index into memory for enforcing the limits, unless
+    /// that's already been done.
+    fn load_usage(&self) -> Result<(), surf::Exception> {
+        if self.usage.lock().unwrap().is_some() {
+            return Ok(());
+        }
+        let mut sizes: HashMap<String, (u128, u64)> = HashMap::new();
+        for md in cacache::list_sync(&self.path) {
+            let md = md?;
+            // A sidecar counts towards the size of the entry it describes,
+            // but reading it isn't a use of that entry.
+            let (key, time) = match md.key.strip_prefix(POLICY_PREFIX) {
+                Some(key) => (key, 0),
+                None => (md.key.as_str(), md.time),
+            };
+            if self.in_namespace(key) {
+                let (last_used, size) = sizes.entry(key.to_string()).or_default();
+                *last_used = time.max(*last_used);
+                *size += md
+                    .metadata
+                    .get("size")
+                    .and_then(serde_json::Value::as_u64)
+                    .unwrap_or(md.size as u64);
+            }
+        }
+        let mut usage = Usage::default();
+        for (key, (time, size)) in sizes {
+            usage.record(&key, time, Some(size));
+        }
+        *self.usage.lock().unwrap() = Some(usage);
+        Ok(())
+    }
+
+    /// Notes a use of `key`, and its new `size` if it was just written, once
+    /// the limits are being enforced.
+    fn record_use(&self, key: &str, size: Option<u64>) {
+        if let Some(usage) = self.usage.lock().unwrap().as_mut() {
+            usage.record(key, now_millis(), size);
+        }
+    }
+
+    async fn remove_key(&self, key: &str) -> Result<(), surf::Exception> {
+        if let Some(usage) = self.usage.lock().unwrap().as_mut() {
+            usage.remove(key);
+        }
+        cacache::remove(&self.path, &policy_key(key)).await?;
+        Ok(cacache::remove(&self.path, key).await?)
+    }
+
+    async fn read_entry(&self, key: &str) -> Result<Option<CacheEntry>, surf::Exception> {
+        let store: Store = match cacache::read(&self.path, key).await {
+            Ok(d) => match Store::from_bytes(&d)? {
+                Some(store) => store,
+                None => {
+                    log::debug!("removing cache entry {} from an older version", key);
+                    self.remove_key(key).await?;
+                    return Ok(None);
+                }
+            },
+            // An entry that's indexed but can't be read has had its content
+            // damaged, or removed, behind cacache's back.
+            Err(_e) => {
+                if cacache::metadata(&self.path, key).await?.is_some() {
+                    log::warn!("removing unreadable cache entry {}", key);
+                    self.remove_key(key).await?;
+                }
+                return Ok(None);
+            }
+        };
+        if !store.is_intact() {
+            log::warn!("removing corrupted cache entry {}", key);
+            self.remove_key(key).await?;
+            return Ok(None);
+        }
+        self.record_use(key, None);
+        Ok(Some(CacheEntry {
+            response: from_store(&store)?,
+            policy: store.policy,
+            stored_at: store.stored_at,
+            namespace: self.namespace.clone(),
+        }))
+    }
+
+    /// Streams every entry stored in this manager's namespace along with its
+    /// key, reading one entry at a time rather than all of them up front.
+    pub fn entries_stream(
+        &self,
+    ) -> impl Stream<Item = Result<(String, CacheEntry), surf::Exception>> + '_ {
+        let keys: Vec<Result<String, surf::Exception>> = match self.index() {
+            Ok(index) => index.into_iter().map(|md| Ok(md.key)).collect(),
+            Err(e) => vec![Err(e)],
+        };
+        stream::iter(keys)
+            .then(move |key| async move {
+                let key = key?;
+                // Entries can be removed between listing and reading them.
+                Ok(self.read_entry(&key).await?.map(|entry| (key, entry)))
+            })
+            .filter_map(|entry| future::ready(entry.transpose()))
+    }
+
+    /// Writes `data` under `key`, recording its size in the index. That goes
+    /// in the entry's metadata: cacache only fills in its own `size` field
+    /// when told the size up front, and then fails to count what's written.
+    async fn write_sized(&self, key: &str, data: &[u8]) -> Result<(), surf::Exception> {
+        let mut writer = cacache::WriteOpts::new()
+            .metadata(serde_json::json!({ "size": data.len() }))
+            .open(&self.path, key)
+            .await?;
+        writer.write_all(data).await?;
+        writer.commit().await?;
+        Ok(())
+    }
+
+    /// Writes an entry and its policy sidecar, without evicting anything.
+    async fn write_entry(
+        &self,
+        key: &str,
+        res: Response,
+        policy: CachePolicy,
+    ) -> Result<Store, surf::Exception> {
+        let store = to_store(res, policy).await?;
+        // Each write is atomic on its own, but the future can be dropped
+        // between them. The entry itself goes first so that can only ever
+        // leave a missing or outdated policy sidecar behind, which
+        // `get_metadata` copes with, rather than a sidecar for an entry
+        // that was never written.
+        let bytes = store.to_bytes()?;
+        self.write_sized(key, &bytes).await?;
+        // The policy also gets its own, much smaller, entry so freshness
+        // checks don't have to pull the whole body off disk.
+        let sidecar = serde_json::to_vec(&Sidecar {
+            schema: SCHEMA_VERSION,
+            policy: &store.policy,
+        })?;
+        self.write_sized(&policy_key(key), &sidecar).await?;
+        self.record_use(key, Some((bytes.len() + sidecar.len()) as u64));
+        Ok(store)
+    }
+
+    /// Evicts the least recently used entries until the cache is back
+    /// within its limits.
+    async fn evict(&self) -> Result<(), surf::Exception> {
+        if self.max_entries.is_none() && self.max_total_bytes.is_none() {
+            return Ok(());
+        }
+        self.load_usage()?;
+        loop {
+            let victim = {
+                let usage = self.usage.lock().unwrap();
+                let usage = usage.as_ref().expect("Usage was just loaded.");
+                let too_many = self
+                    .max_entries
+                    .is_some_and(|max| usage.entries.len() > max);
+                let too_big = self
+                    .max_total_bytes
+                    .is_some_and(|max| usage.total_bytes > max);
+                if !too_many && !too_big {
+                    break;
+                }
+                usage.least_recently_used()
+            };
+            match victim {
+                Some(key) => self.remove_key(&key).await?,
+                None => break,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl CacheManager for CACacheManager {
+    async fn get(&self, req: &Request) -> Result<Option<CacheEntry>, surf::Exception> {
+        self.read_entry(&self.key(req)).await
+    }
+
+    async fn put(
+        &self,
+        req: &Request,
+        res: Response,
+        policy: CachePolicy,
+    ) -> Result<Response, surf::Exception> {
+        let store = self.write_entry(&self.key(req), res, policy).await?;
+        self.evict().await?;
+        from_store(&store)
+    }
+
+    async fn delete(&self, req: &Request) -> Result<(), surf::Exception> {
+        self.remove_key(&self.key(req)).await
+    }
+
+    async fn get_metadata(&self, req: &Request) -> Result<Option<CachePolicy>, surf::Exception> {
+        let key = self.key(req);
+        let store = match cacache::metadata(&self.path, &key).await? {
+            Some(store) => store,
+            None => return Ok(None),
+        };
+        // A sidecar written before the entry it describes is left over from
+        // an older entry, or from a put that never finished.
+        match cacache::metadata(&self.path, &policy_key(&key)).await? {
+            Some(sidecar) if sidecar.time >= store.time => self.read_policy(&key).await,
+            _ => Ok(self.get(req).await?.map(|entry| entry.policy)),
+        }
+    }
+
+    async fn contains(&self, req: &Request) -> Result<bool, surf::Exception> {
+        Ok(cacache::metadata(&self.path, &self.key(req))
+            .await?
+            .is_some())
+    }
+
+    async fn list(&self) -> Result<Vec<EntryInfo>, surf::Exception> {
+        let mut entries = Vec::new();
+        for md in self.index()? {
+            let fresh_until = match self.read_policy(&md.key).await? {
+                Some(policy) => policy.fresh_until(),
+                None => continue,
+            };
+            entries.push(EntryInfo {
+                stored_at: UNIX_EPOCH + Duration::from_millis(md.time as u64),
+                fresh_until,
+                key: md.key,
+            });
+        }
+        Ok(entries)
+    }
+
+    async fn purge_expired(&self) -> Result<usize, surf::Exception> {
+        let now = clock::now();
+        let mut purged = 0;
+        for md in self.index()? {
+            if let Some(policy) = self.read_policy(&md.key).await? {
+                if policy.fresh_until() + policy.stale_grace() < now {
+                    self.remove_key(&md.key).await?;
+                    purged += 1;
+                }
+            }
+        }
+        Ok(purged)
+    }
+
+    async fn delete_matching(
+        &self,
+        predicate: &(dyn for<'k> Fn(&'k str) -> bool + Send + Sync),
+    ) -> Result<usize, surf::Exception> {
+        let mut deleted = 0;
+        for md in self.index()? {
+            if predicate(&md.key) {
+                self.remove_key(&md.key).await?;
+                deleted += 1;
+            }
+        }
+        Ok(deleted)
+    }
+
+    async fn entries(&self) -> Result<Vec<(String, CacheEntry)>, surf::Exception> {
+        self.entries_stream().try_collect().await
+    }
+
+    async fn migrate(&self) -> Result<usize, surf::Exception> {
+        let mut dropped = 0;
+        for md in self.index()? {
+            if let Ok(d) = cacache::read(&self.path, &md.key).await {
+                if !Store::is_current(&d) {
+                    self.remove_key(&md.key).await?;
+                    dropped += 1;
+                }
+            }
+        }
+        Ok(dropped)
+    }
+
+    async fn stats(&self, req: &Request) -> Result<Option<EntryStats>, surf::Exception> {
+        match cacache::read(&self.path, &self.key(req)).await {
+            Ok(d) => Ok(Store::from_bytes(&d)?.map(|store| store.stats())),
+            Err(_e) => Ok(None),
+        }
+    }
+
+    async fn get_many(&self, reqs: &[Request]) -> Result<Vec<Option<CacheEntry>>, surf::Exception> {
+        // Reads don't depend on each other, so they all go at once.
+        future::try_join_all(reqs.iter().map(|req| self.get(req))).await
+    }
+
+    async fn put_many(
+        &self,
+        entries: Vec<(Request, Response, CachePolicy)>,
+    ) -> Result<Vec<Response>, surf::Exception> {
+        let stores =
+            future::try_join_all(entries.into_iter().map(|(req, res, policy)| async move {
+                self.write_entry(&self.key(&req), res, policy).await
+            }))
+            .await?;
+        // Evicting once, after everything's written, rather than after
+        // each write.
+        self.evict().await?;
+        stores.iter().map(from_store).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use futures::io::AsyncReadExt;
+
+    use surf::middleware::Body;
+
+    use super::*;
+
+    fn get(uri: &str) -> Request {
+        http::Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    fn response(body: &str) -> Response {
+        let mut res = http::Response::builder();
+        res.header("Cache-Control", "max-age=60");
+        res.body(Body::from(body.as_bytes().to_vec())).unwrap()
+    }
+
+    /// Stores `body` for `uri`, a little after whatever was stored before,
+    /// so entries' use times never tie.
+    async fn put(manager: &CACacheManager, uri: &str, body: &str) {
+        thread::sleep(Duration::from_millis(2));
+        let req = get(uri);
+        let policy = CachePolicy::new(&req, &response(""));
+        manager.put(&req, response(body), policy).await.unwrap();
+    }
+
+    async fn stored(manager: &CACacheManager, uri: &str) -> bool {
+        manager.contains(&get(uri)).await.unwrap()
+    }
+
+    #[async_attributes::test]
+    async fn evicts_the_oldest_entry_past_max_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = CACacheManager::new(dir.path().to_str().unwrap()).with_max_entries(2);
+        put(&manager, "http://example.com/1", "one").await;
+        put(&manager, "http://example.com/2", "two").await;
+        put(&manager, "http://example.com/3", "three").await;
+
+        assert!(!stored(&manager, "http://example.com/1").await);
+        assert!(stored(&manager, "http://example.com/2").await);
+        assert!(stored(&manager, "http://example.com/3").await);
+    }
+
+    #[async_attributes::test]
+    async fn evicts_the_least_recently_read_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = CACacheManager::new(dir.path().to_str().unwrap()).with_max_entries(2);
+        put(&manager, "http://example.com/1", "one").await;
+        put(&manager, "http://example.com/2", "two").await;
+        thread::sleep(Duration::from_millis(2));
+        manager.get(&get("http://example.com/1")).await.unwrap();
+        put(&manager, "http://example.com/3", "three").await;
+
+        assert!(stored(&manager, "http://example.com/1").await);
+        assert!(!stored(&manager, "http://example.com/2").await);
+        assert!(stored(&manager, "http://example.com/3").await);
+    }
+
+    #[async_attributes::test]
+    async fn removes_an_entry_whose_content_was_tampered_with() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = CACacheManager::new(dir.path().to_str().unwrap());
+        put(&manager, "http://example.com/1", "one").await;
+        let key = manager.key(&get("http://example.com/1"));
+        assert!(cacache::metadata(&manager.path, &key)
+            .await
+            .unwrap()
+            .is_some());
+
+        for file in walk(&dir.path().join("content-v2")) {
+            std::fs::write(file, "tampered").unwrap();
+        }
+
+        assert!(manager
+            .get(&get("http://example.com/1"))
+            .await
+            .unwrap()
+            .is_none());
+        assert!(cacache::metadata(&manager.path, &key)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[async_attributes::test]
+    async fn get_metadata_reads_the_policy_without_the_body() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = CACacheManager::new(dir.path().to_str().unwrap());
+        put(&manager, "http://example.com/1", "one").await;
+        let req = get("http://example.com/1");
+        // With the body gone, only the sidecar is left to read.
+        let md = cacache::metadata(&manager.path, &manager.key(&req))
+            .await
+            .unwrap()
+            .unwrap();
+        cacache::remove_hash(&manager.path, &md.integrity)
+            .await
+            .unwrap();
+
+        let policy = manager.get_metadata(&req).await.unwrap().unwrap();
+        assert_eq!(policy.response_headers()["cache-control"], "max-age=60");
+    }
+
+    #[async_attributes::test]
+    async fn sidecars_from_other_versions_are_misses() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = CACacheManager::new(dir.path().to_str().unwrap());
+        put(&manager, "http://example.com/1", "one").await;
+        let req = get("http://example.com/1");
+        let key = policy_key(&manager.key(&req));
+        assert!(manager.get_metadata(&req).await.unwrap().is_some());
+
+        let sidecar = cacache::read(&manager.path, &key).await.unwrap();
+        let mut sidecar: serde_json::Value = serde_json::from_slice(&sidecar).unwrap();
+        sidecar["schema"] = (SCHEMA_VERSION + 1).into();
+        sidecar["policy"] = "a layout from the future".into();
+        manager
+            .write_sized(&key, &serde_json::to_vec(&sidecar).unwrap())
+            .await
+            .unwrap();
+
+        assert!(manager.get_metadata(&req).await.unwrap().is_none());
+        assert!(manager.list().await.unwrap().is_empty());
+    }
+
+    #[async_attributes::test]
+    async fn stores_header_values_that_arent_text() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = CACacheManager::new(dir.path().to_str().unwrap());
+        let req = get("http://example.com/1");
+        let mut res = response("one");
+        let latin1 = http::HeaderValue::from_bytes(b"caf\xe9").unwrap();
+        res.headers_mut().insert("x-latin1", latin1.clone());
+        res.headers_mut()
+            .insert("x-utf8", http::HeaderValue::from_str("café").unwrap());
+        let policy = CachePolicy::new(&req, &res);
+        manager.put(&req, res, policy).await.unwrap();
+
+        let entry = manager.get(&req).await.unwrap().unwrap();
+        assert_eq!(entry.response.headers()["x-latin1"], latin1);
+        assert_eq!(
+            entry.response.headers()["x-utf8"].as_bytes(),
+            "café".as_bytes()
+        );
+    }
+
+    fn version(version: u8) -> Response {
+        let mut res = http::Response::builder();
+        res.header("Cache-Control", "max-age=60");
+        res.header("X-Version", (version as char).to_string());
+        res.body(Body::from(vec![version; 16 * 1024])).unwrap()
+    }
+
+    #[async_attributes::test]
+    async fn reads_racing_a_write_see_the_old_entry_or_the_new_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = CACacheManager::new(dir.path().to_str().unwrap());
+        let req = get("http://example.com/1");
+        let policy = CachePolicy::new(&req, &version(b'a'));
+        manager.put(&req, version(b'a'), policy).await.unwrap();
+
+        let writer = {
+            let manager = manager.clone();
+            async_std::task::spawn(async move {
+                let req = get("http://example.com/1");
+                for v in b'b'..=b'k' {
+                    let policy = CachePolicy::new(&req, &version(v));
+                    manager.put(&req, version(v), policy).await.unwrap();
+                }
+            })
+        };
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let manager = manager.clone();
+                async_std::task::spawn(async move {
+                    let req = get("http://example.com/1");
+                    for _ in 0..20 {
+                        let entry = manager.get(&req).await.unwrap().unwrap();
+                        let version = entry.response.headers()["x-version"].as_bytes()[0];
+                        assert_eq!(
+                            entry.policy.response_headers()["x-version"].as_bytes(),
+                            [version]
+                        );
+                        let mut body = Vec::new();
+                        entry
+                            .response
+                            .into_body()
+                            .read_to_end(&mut body)
+                            .await
+                            .unwrap();
+                        assert_eq!(body.len(), 16 * 1024);
+                        assert!(body.iter().all(|&byte| byte == version));
+                    }
+                })
+            })
+            .collect();
+        writer.await;
+        for reader in readers {
+            reader.await;
+        }
+    }
+
+    /// Every file under `dir`.
+    fn walk(dir: &std::path::Path) -> Vec<std::path::PathBuf> {
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                files.extend(walk(&path));
+            } else {
+                files.push(path);
+            }
+        }
+        files
+    }
+
+    /// Bytes taken up by everything stored under `path`, according to
+    /// cacache's index.
+    fn bytes_on_disk(path: &str) -> u64 {
+        let manager = CACacheManager::new(path);
+        manager.load_usage().unwrap();
+        let usage = manager.usage.lock().unwrap();
+        usage.as_ref().unwrap().total_bytes
+    }
+
+    #[async_attributes::test]
+    async fn evicts_the_oldest_entries_past_max_total_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let body = "x".repeat(1000);
+        put(&CACacheManager::new(path), "http://example.com/0", &body).await;
+        // Room for three and a half entries, since their sizes vary a little
+        // with their timestamps.
+        let budget = bytes_on_disk(path) * 7 / 2;
+
+        let manager = CACacheManager::new(path).with_max_total_bytes(budget);
+        for i in 1..=4 {
+            put(&manager, &format!("http://example.com/{}", i), &body).await;
+        }
+
+        assert!(!stored(&manager, "http://example.com/0").await);
+        assert!(!stored(&manager, "http://example.com/1").await);
+        for i in 2..=4 {
+            assert!(stored(&manager, &format!("http://example.com/{}", i)).await);
+        }
+    }
+
+    #[async_attributes::test]
+    async fn counts_policy_sidecars_towards_max_total_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let manager = CACacheManager::new(path).with_max_total_bytes(u64::MAX);
+        put(&manager, "http://example.com/1", "one").await;
+        put(&manager, "http://example.com/2", "two").await;
+
+        let (mut entries, mut sidecars) = (0, 0);
+        for md in cacache::list_sync(path) {
+            let md = md.unwrap();
+            let size = md.metadata["size"].as_u64().unwrap();
+            if md.key.starts_with(POLICY_PREFIX) {
+                sidecars += size;
+            } else {
+                entries += size;
+            }
+        }
+        assert!(sidecars > 0);
+        let usage = manager.usage.lock().unwrap();
+        assert_eq!(usage.as_ref().unwrap().total_bytes, entries + sidecars);
+        assert_eq!(bytes_on_disk(path), entries + sidecars);
+    }
+
+    #[async_attributes::test]
+    async fn stores_entries_under_its_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("cache");
+        let manager = CACacheManager::new(path.to_str().unwrap());
+        put(&manager, "http://example.com/1", "one").await;
+
+        assert!(!walk(&path).is_empty());
+        assert!(
+            stored(
+                &CACacheManager::new(path.to_str().unwrap()),
+                "http://example.com/1"
+            )
+            .await
+        );
+    }
+
+    #[async_attributes::test]
+    async fn namespaces_sharing_a_path_keep_their_entries_apart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let a = CACacheManager::new(path).with_namespace("a");
+        let b = CACacheManager::new(path).with_namespace("b");
+        put(&a, "http://example.com/1", "from a").await;
+        put(&b, "http://example.com/1", "from b").await;
+
+        for (manager, body) in &[(&a, "from a"), (&b, "from b")] {
+            let entry = manager
+                .get(&get("http://example.com/1"))
+                .await
+                .unwrap()
+                .unwrap();
+            let mut stored = String::new();
+            entry
+                .response
+                .into_body()
+                .read_to_string(&mut stored)
+                .await
+                .unwrap();
+            assert_eq!(&stored, body);
+            assert_eq!(manager.list().await.unwrap().len(), 1);
+        }
+        a.delete(&get("http://example.com/1")).await.unwrap();
+        assert!(stored(&b, "http://example.com/1").await);
+    }
+
+    #[async_attributes::test]
+    async fn keeps_every_value_of_a_repeated_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = CACacheManager::new(dir.path().to_str().unwrap());
+        let req = get("http://example.com/1");
+        let mut res = response("one");
+        for cookie in &["a=1", "b=2"] {
+            res.headers_mut()
+                .append("set-cookie", http::HeaderValue::from_static(cookie));
+        }
+        let policy = CachePolicy::new(&req, &res);
+        manager.put(&req, res, policy).await.unwrap();
+
+        let entry = manager.get(&req).await.unwrap().unwrap();
+        let cookies: Vec<_> = entry
+            .response
+            .headers()
+            .get_all("set-cookie")
+            .iter()
+            .collect();
+        assert_eq!(cookies, vec!["a=1", "b=2"]);
+    }
+
+    #[async_attributes::test]
+    async fn put_hands_back_what_a_later_get_sees() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = CACacheManager::new(dir.path().to_str().unwrap());
+        let req = get("http://example.com/1");
+        let policy = CachePolicy::new(&req, &response(""));
+        let put = manager.put(&req, response("one"), policy).await.unwrap();
+        let got = manager.get(&req).await.unwrap().unwrap().response;
+
+        assert_eq!(put.status(), got.status());
+        assert_eq!(put.headers(), got.headers());
+        let mut put_body = Vec::new();
+        put.into_body().read_to_end(&mut put_body).await.unwrap();
+        let mut got_body = Vec::new();
+        got.into_body().read_to_end(&mut got_body).await.unwrap();
+        assert_eq!(put_body, b"one");
+        assert_eq!(put_body, got_body);
+    }
+
+    #[async_attributes::test]
+    async fn each_get_can_read_the_whole_body() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = CACacheManager::new(dir.path().to_str().unwrap());
+        put(&manager, "http://example.com/1", "one").await;
+
+        let mut bodies = Vec::new();
+        for _ in 0..2 {
+            let entry = manager
+                .get(&get("http://example.com/1"))
+                .await
+                .unwrap()
+                .unwrap();
+            let mut body = String::new();
+            entry
+                .response
+                .into_body()
+                .read_to_string(&mut body)
+                .await
+                .unwrap();
+            bodies.push(body);
+        }
+        assert_eq!(bodies, vec!["one", "one"]);
+    }
+
+    #[async_attributes::test]
+    async fn stats_record_the_stored_body_length() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = CACacheManager::new(dir.path().to_str().unwrap());
+        let body = "x".repeat(1234);
+        put(&manager, "http://example.com/1", &body).await;
+
+        let stats = manager
+            .stats(&get("http://example.com/1"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stats.body_len, Some(body.len() as u64));
+        assert!(manager
+            .stats(&get("http://example.com/2"))
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[async_attributes::test]
+    async fn stored_at_moves_forward_when_an_entry_is_stored_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = CACacheManager::new(dir.path().to_str().unwrap());
+        let before = clock::now();
+        put(&manager, "http://example.com/1", "one").await;
+        let first = manager
+            .get(&get("http://example.com/1"))
+            .await
+            .unwrap()
+            .unwrap()
+            .stored_at;
+        assert!(first >= before);
+
+        put(&manager, "http://example.com/1", "two").await;
+        let second = manager
+            .get(&get("http://example.com/1"))
+            .await
+            .unwrap()
+            .unwrap()
+            .stored_at;
+        assert!(second > first);
+    }
+
+    #[async_attributes::test]
+    async fn entries_hands_back_every_entry_with_its_body() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = CACacheManager::new(dir.path().to_str().unwrap());
+        put(&manager, "http://example.com/1", "one").await;
+        put(&manager, "http://example.com/2", "two").await;
+
+        let mut entries = manager.entries().await.unwrap();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut found = Vec::new();
+        for (key, entry) in entries {
+            let mut body = String::new();
+            entry
+                .response
+                .into_body()
+                .read_to_string(&mut body)
+                .await
+                .unwrap();
+            found.push((key, body));
+        }
+        assert_eq!(
+            found,
+            vec![
+                ("GET:http://example.com/1".to_string(), "one".to_string()),
+                ("GET:http://example.com/2".to_string(), "two".to_string()),
+            ]
+        );
+    }
+
+    #[async_attributes::test]
+    async fn put_many_and_get_many_keep_the_order_they_were_given() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = CACacheManager::new(dir.path().to_str().unwrap());
+        let uris: Vec<String> = (0..10)
+            .map(|i| format!("http://example.com/{}", i))
+            .collect();
+        let entries = uris
+            .iter()
+            .map(|uri| {
+                let req = get(uri);
+                let policy = CachePolicy::new(&req, &response(""));
+                (req, response(uri), policy)
+            })
+            .collect();
+        let stored = manager.put_many(entries).await.unwrap();
+        assert_eq!(stored.len(), 10);
+
+        let reqs: Vec<Request> = uris.iter().map(|uri| get(uri)).collect();
+        let found = manager.get_many(&reqs).await.unwrap();
+        assert_eq!(found.len(), 10);
+        for (uri, entry) in uris.iter().zip(found) {
+            let mut body = String::new();
+            entry
+                .unwrap()
+                .response
+                .into_body()
+                .read_to_string(&mut body)
+                .await
+                .unwrap();
+            assert_eq!(&body, uri);
+        }
+    }
+}
@@ -0,0 +1,549 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+use async_std::fs;
+use async_trait::async_trait;
+use futures::io::{AsyncReadExt, AsyncWriteExt};
+use futures::stream::StreamExt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use surf::middleware::{Body, Request, Response};
+
+use super::req_key;
+use super::store::StoredValue;
+use crate::{clock, CacheEntry, CacheManager, CachePolicy, EntryInfo};
+
+/// Implements [`CacheManager`] by writing each entry to its own
+/// pretty-printed JSON file in a directory, with text bodies stored as text.
+/// Much slower than [`CACacheManager`](crate::CACacheManager), but the
+/// cache can be inspected, and edited, with nothing more than a text editor.
+///
+/// Files are named after their keys, with anything but ASCII letters,
+/// digits, `-`, `_` and `.` percent-encoded. Keys too long to make a valid
+/// file name are named by their SHA-256 instead. Entries are written to a
+/// temporary file and renamed into place, so readers never see half of one.
+/// Files that can't be read as an entry, or were written under an older
+/// layout, are treated as misses.
+#[derive(Debug, Clone)]
+pub struct FsManager {
+    /// Directory the entries are stored in.
+    pub path: PathBuf,
+    /// Prepended to every key, so several managers can share one directory
+    /// without their entries colliding.
+    pub namespace: Option<String>,
+}
+
+/// Bumped whenever the layout of `FsEntry` changes incompatibly.
+const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Deserialize, Serialize)]
+struct FsEntry {
+    /// Always `SCHEMA_VERSION` when written. Entries from before versioning
+    /// was added read as `0`.
+    #[serde(default)]
+    schema: u32,
+    key: String,
+    stored_at: SystemTime,
+    status: u16,
+    version: String,
+    headers: Vec<(String, Vec<StoredValue>)>,
+    /// The body, if it's valid UTF-8.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    body_text: Option<String>,
+    /// The body, if it isn't.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    body_bytes: Option<Vec<u8>>,
+    policy: CachePolicy,
+}
+
+/// Just enough of an entry to tell which version wrote it, even if its
+/// layout can't be read anymore.
+#[derive(Deserialize)]
+struct Versioned {
+    #[serde(default)]
+    schema: u32,
+    key: String,
+}
+
+/// Longest file name most filesystems accept, leaving room for `.json`.
+const MAX_FILE_NAME: usize = 250;
+
+/// Counts writes, so each one gets a temporary file of its own.
+static WRITES: AtomicUsize = AtomicUsize::new(0);
+
+/// The contents of the file at `path`, or `None` if there isn't one.
+async fn read_file(path: &Path) -> Result<Option<Vec<u8>>, surf::Exception> {
+    match fs::read(path).await {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn remove_file(path: &Path) -> Result<(), surf::Exception> {
+    match fs::remove_file(path).await {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Reads an entry back, or `None` if `bytes` aren't one written under the
+/// current schema version.
+fn parse(bytes: Vec<u8>) -> Option<FsEntry> {
+    match serde_json::from_slice::<FsEntry>(&bytes) {
+        Ok(entry) if entry.schema == SCHEMA_VERSION => Some(entry),
+        Ok(entry) => {
+            log::debug!("ignoring cache entry {} from an older version", entry.key);
+            None
+        }
+        Err(e) => {
+            log::warn!("ignoring unreadable cache entry: {}", e);
+            None
+        }
+    }
+}
+
+/// Turns `key` into a file name that's safe on any platform.
+fn file_name(key: &str) -> String {
+    let mut name = String::with_capacity(key.len());
+    for byte in key.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' => name.push(byte as char),
+            _ => name.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    if name.len() > MAX_FILE_NAME || name.starts_with('.') {
+        let mut hasher = Sha256::new();
+        hasher.input(key.as_bytes());
+        name = format!("{:x}", hasher.result());
+    }
+    name.push_str(".json");
+    name
+}
+
+impl FsManager {
+    /// Creates a manager that stores its entries under `path`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FsManager {
+            path: path.into(),
+            namespace: None,
+        }
+    }
+
+    /// Prefixes every key this manager uses with `namespace`.
+    pub fn with_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = Some(namespace.into());
+        self
+    }
+
+    fn key(&self, req: &Request) -> String {
+        req_key(self.namespace.as_deref(), req)
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.path.join(file_name(key))
+    }
+
+    /// Fresh path to write an entry to before it's renamed into place.
+    /// Concurrent writes of the same key each get their own, so neither can
+    /// truncate or interleave with the other's file.
+    fn temp_path(&self) -> PathBuf {
+        self.path.join(format!(
+            "{}-{}.tmp",
+            std::process::id(),
+            WRITES.fetch_add(1, Ordering::SeqCst)
+        ))
+    }
+
+    async fn read_entry(&self, key: &str) -> Result<Option<FsEntry>, surf::Exception> {
+        Ok(read_file(&self.entry_path(key)).await?.and_then(parse))
+    }
+
+    async fn remove_entry(&self, key: &str) -> Result<(), surf::Exception> {
+        remove_file(&self.entry_path(key)).await
+    }
+
+    /// Every entry file in the directory, whichever namespace it's in and
+    /// whether or not it can be read.
+    async fn files(&self) -> Result<Vec<PathBuf>, surf::Exception> {
+        let mut dir = match fs::read_dir(&self.path).await {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut files = Vec::new();
+        while let Some(file) = dir.next().await {
+            let path: PathBuf = file?.path().into();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                files.push(path);
+            }
+        }
+        Ok(files)
+    }
+
+    /// Every readable entry in this manager's namespace.
+    async fn all_entries(&self) -> Result<Vec<FsEntry>, surf::Exception> {
+        let mut entries = Vec::new();
+        for path in self.files().await? {
+            // Files removed since the directory was listed are skipped too.
+            if let Some(entry) = read_file(&path).await?.and_then(parse) {
+                if self.in_namespace(&entry.key) {
+                    entries.push(entry);
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    fn in_namespace(&self, key: &str) -> bool {
+        match &self.namespace {
+            Some(namespace) => key.starts_with(&format!("{}:", namespace)),
+            None => true,
+        }
+    }
+
+    fn to_cache_entry(&self, entry: FsEntry) -> CacheEntry {
+        let mut res = http::Response::builder();
+        res.status(entry.status);
+        res.version(match entry.version.as_str() {
+            "HTTP/0.9" => http::Version::HTTP_09,
+            "HTTP/1.0" => http::Version::HTTP_10,
+            "HTTP/2.0" => http::Version::HTTP_2,
+            _ => http::Version::HTTP_11,
+        });
+        let headers = res.headers_mut().expect("Couldn't get headers.");
+        for (name, values) in entry.headers.iter() {
+            let name = match http::header::HeaderName::from_bytes(name.as_bytes()) {
+                Ok(name) => name,
+                Err(_) => continue,
+            };
+            for value in values {
+                if let Ok(value) = http::HeaderValue::from_bytes(value.as_bytes()) {
+                    headers.append(&name, value);
+                }
+            }
+        }
+        let body = match (entry.body_text, entry.body_bytes) {
+            (Some(text), _) => text.into_bytes(),
+            (None, Some(bytes)) => bytes,
+            (None, None) => Vec::new(),
+        };
+        if !entry.policy.is_head() {
+            headers.insert(http::header::CONTENT_LENGTH, body.len().into());
+        }
+        CacheEntry {
+            response: res.body(Body::from(body)).unwrap(),
+            policy: entry.policy,
+            stored_at: entry.stored_at,
+            namespace: self.namespace.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl CacheManager for FsManager {
+    async fn get(&self, req: &Request) -> Result<Option<CacheEntry>, surf::Exception> {
+        Ok(self
+            .read_entry(&self.key(req))
+            .await?
+            .map(|entry| self.to_cache_entry(entry)))
+    }
+
+    async fn put(
+        &self,
+        req: &Request,
+        res: Response,
+        policy: CachePolicy,
+    ) -> Result<Response, surf::Exception> {
+        let (parts, mut body) = res.into_parts();
+        let mut bytes = Vec::new();
+        body.read_to_end(&mut bytes).await?;
+        let mut headers = Vec::new();
+        for name in parts.headers.keys() {
+            let values = parts.headers.get_all(name).iter().map(StoredValue::new);
+            headers.push((name.as_str().to_string(), values.collect()));
+        }
+        let (body_text, body_bytes) = match String::from_utf8(bytes) {
+            Ok(text) => (Some(text), None),
+            Err(e) => (None, Some(e.into_bytes())),
+        };
+        let key = self.key(req);
+        let entry = FsEntry {
+            schema: SCHEMA_VERSION,
+            key: key.clone(),
+            stored_at: policy.response_time(),
+            status: parts.status.as_u16(),
+            version: format!("{:?}", parts.version),
+            headers,
+            body_text,
+            body_bytes,
+            policy,
+        };
+        fs::create_dir_all(&self.path).await?;
+        let path = self.entry_path(&key);
+        let tmp = self.temp_path();
+        let mut file = fs::File::create(&tmp).await?;
+        file.write_all(&serde_json::to_vec_pretty(&entry)?).await?;
+        file.sync_all().await?;
+        fs::rename(&tmp, &path).await?;
+        Ok(self.to_cache_entry(entry).response)
+    }
+
+    async fn delete(&self, req: &Request) -> Result<(), surf::Exception> {
+        self.remove_entry(&self.key(req)).await
+    }
+
+    async fn list(&self) -> Result<Vec<EntryInfo>, surf::Exception> {
+        Ok(self
+            .all_entries()
+            .await?
+            .into_iter()
+            .map(|entry| EntryInfo {
+                stored_at: entry.stored_at,
+                fresh_until: entry.policy.fresh_until(),
+                key: entry.key,
+            })
+            .collect())
+    }
+
+    async fn purge_expired(&self) -> Result<usize, surf::Exception> {
+        let now = clock::now();
+        let mut purged = 0;
+        for entry in self.all_entries().await? {
+            if entry.policy.fresh_until() + entry.policy.stale_grace() < now {
+                self.remove_entry(&entry.key).await?;
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
+
+    async fn delete_matching(
+        &self,
+        predicate: &(dyn for<'k> Fn(&'k str) -> bool + Send + Sync),
+    ) -> Result<usize, surf::Exception> {
+        let mut deleted = 0;
+        for entry in self.all_entries().await? {
+            if predicate(&entry.key) {
+                self.remove_entry(&entry.key).await?;
+                deleted += 1;
+            }
+        }
+        Ok(deleted)
+    }
+
+    async fn entries(&self) -> Result<Vec<(String, CacheEntry)>, surf::Exception> {
+        Ok(self
+            .all_entries()
+            .await?
+            .into_iter()
+            .map(|entry| (entry.key.clone(), self.to_cache_entry(entry)))
+            .collect())
+    }
+
+    async fn migrate(&self) -> Result<usize, surf::Exception> {
+        let mut dropped = 0;
+        for path in self.files().await? {
+            let bytes = match read_file(&path).await? {
+                Some(bytes) => bytes,
+                None => continue,
+            };
+            // Unreadable files can't be placed in a namespace, so they're
+            // left to whichever manager wrote them.
+            let key = match serde_json::from_slice::<Versioned>(&bytes) {
+                Ok(versioned) if versioned.schema != SCHEMA_VERSION => versioned.key,
+                _ => continue,
+            };
+            if self.in_namespace(&key) {
+                remove_file(&path).await?;
+                dropped += 1;
+            }
+        }
+        Ok(dropped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use surf::middleware::Body;
+
+    use super::*;
+
+    fn get(uri: &str) -> Request {
+        http::Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    fn response(body: Vec<u8>) -> Response {
+        let mut res = http::Response::builder();
+        res.header("Cache-Control", "max-age=60");
+        res.header("Set-Cookie", "a=1");
+        res.header("Set-Cookie", "b=2");
+        res.body(Body::from(body)).unwrap()
+    }
+
+    async fn put(manager: &FsManager, uri: &str, body: Vec<u8>) {
+        let req = get(uri);
+        let policy = CachePolicy::new(&req, &response(Vec::new()));
+        manager.put(&req, response(body), policy).await.unwrap();
+    }
+
+    async fn body_bytes(res: Response) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        res.into_body().read_to_end(&mut bytes).await.unwrap();
+        bytes
+    }
+
+    #[test]
+    fn file_names_escape_everything_but_safe_characters() {
+        assert_eq!(
+            file_name("GET:http://example.com/a b?c=d"),
+            "GET%3Ahttp%3A%2F%2Fexample.com%2Fa%20b%3Fc%3Dd.json"
+        );
+        assert_eq!(file_name("a-b_c.d"), "a-b_c.d.json");
+    }
+
+    #[test]
+    fn file_names_hash_keys_that_wouldnt_be_valid() {
+        let long = file_name(&"x".repeat(MAX_FILE_NAME + 1));
+        assert_eq!(long.len(), 64 + ".json".len());
+        let hidden = file_name(".hidden");
+        assert_eq!(hidden.len(), 64 + ".json".len());
+        assert_ne!(long, hidden);
+    }
+
+    #[async_attributes::test]
+    async fn round_trips_text_and_binary_bodies() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = FsManager::new(dir.path());
+        put(&manager, "http://example.com/text", b"hello".to_vec()).await;
+        put(
+            &manager,
+            "http://example.com/binary",
+            vec![0, 159, 146, 150],
+        )
+        .await;
+
+        let text = manager
+            .get(&get("http://example.com/text"))
+            .await
+            .unwrap()
+            .unwrap();
+        let cookies: Vec<_> = text
+            .response
+            .headers()
+            .get_all("set-cookie")
+            .iter()
+            .collect();
+        assert_eq!(cookies, vec!["a=1", "b=2"]);
+        assert_eq!(body_bytes(text.response).await, b"hello");
+        let binary = manager
+            .get(&get("http://example.com/binary"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(body_bytes(binary.response).await, vec![0, 159, 146, 150]);
+    }
+
+    #[async_attributes::test]
+    async fn stores_header_values_that_arent_text() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = FsManager::new(dir.path());
+        let req = get("http://example.com/");
+        let mut res = response(b"hello".to_vec());
+        let latin1 = http::HeaderValue::from_bytes(b"caf\xe9").unwrap();
+        res.headers_mut().insert("x-latin1", latin1.clone());
+        let policy = CachePolicy::new(&req, &res);
+        manager.put(&req, res, policy).await.unwrap();
+
+        let entry = manager.get(&req).await.unwrap().unwrap();
+        assert_eq!(entry.response.headers()["x-latin1"], latin1);
+    }
+
+    #[async_attributes::test]
+    async fn skips_files_that_arent_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = FsManager::new(dir.path());
+        put(&manager, "http://example.com/good", b"good".to_vec()).await;
+        let bad = manager.entry_path(&manager.key(&get("http://example.com/bad")));
+        std::fs::write(bad, "not json").unwrap();
+
+        assert!(manager
+            .get(&get("http://example.com/bad"))
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(manager.list().await.unwrap().len(), 1);
+        assert_eq!(manager.entries().await.unwrap().len(), 1);
+        assert_eq!(manager.purge_expired().await.unwrap(), 0);
+    }
+
+    #[async_attributes::test]
+    async fn treats_entries_from_older_versions_as_misses() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = FsManager::new(dir.path());
+        put(&manager, "http://example.com/old", b"old".to_vec()).await;
+        put(&manager, "http://example.com/new", b"new".to_vec()).await;
+        let old = manager.entry_path(&manager.key(&get("http://example.com/old")));
+        let mut entry: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&old).unwrap()).unwrap();
+        entry.as_object_mut().unwrap().remove("schema");
+        std::fs::write(&old, serde_json::to_vec(&entry).unwrap()).unwrap();
+
+        assert!(manager
+            .get(&get("http://example.com/old"))
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(manager.migrate().await.unwrap(), 1);
+        assert!(!old.exists());
+        assert!(manager
+            .contains(&get("http://example.com/new"))
+            .await
+            .unwrap());
+    }
+
+    #[async_attributes::test]
+    async fn concurrent_writes_of_one_key_never_tear_the_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = FsManager::new(dir.path());
+        let writers: Vec<_> = (b'a'..=b'h')
+            .map(|byte| {
+                let manager = manager.clone();
+                async_std::task::spawn(async move {
+                    for _ in 0..5 {
+                        put(&manager, "http://example.com/1", vec![byte; 64 * 1024]).await;
+                    }
+                })
+            })
+            .collect();
+        let reader = {
+            let manager = manager.clone();
+            async_std::task::spawn(async move {
+                for _ in 0..20 {
+                    if let Some(entry) = manager.get(&get("http://example.com/1")).await.unwrap() {
+                        let body = body_bytes(entry.response).await;
+                        assert_eq!(body.len(), 64 * 1024);
+                        assert!(body.iter().all(|&byte| byte == body[0]));
+                    }
+                }
+            })
+        };
+        for writer in writers {
+            writer.await;
+        }
+        reader.await;
+
+        let entry = manager
+            .get(&get("http://example.com/1"))
+            .await
+            .unwrap()
+            .unwrap();
+        let body = body_bytes(entry.response).await;
+        assert_eq!(body.len(), 64 * 1024);
+        assert!(body.iter().all(|&byte| byte == body[0]));
+        // Every temporary file was renamed into place.
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
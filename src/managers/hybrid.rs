@@ -0,0 +1,347 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use futures::io::AsyncReadExt;
+use surf::middleware::{Body, Request, Response};
+
+use super::{CACacheManager, MemoryManager};
+use crate::{clock, CacheEntry, CacheManager, CachePolicy, EntryInfo, EntryStats};
+
+/// Keeps small bodies in memory and spills larger ones to a
+/// [`CACacheManager`] on disk.
+///
+/// Policies for spilled entries stay in memory too, so freshness checks
+/// never have to read them back from disk. They only check the disk still
+/// has the entry, since it may have evicted it to stay within its limits.
+/// Since that index isn't persisted, entries spilled by a previous process
+/// aren't found again.
+///
+/// Spilled entries are only indexed once they're fully on disk, so a `get`
+/// racing a `put` never sees a partial entry. It can miss, though, while an
+/// entry whose body grew past the threshold moves from memory to disk.
+#[derive(Debug, Clone)]
+pub struct HybridManager {
+    /// Bodies of at least this many bytes go to disk.
+    pub spill_threshold: usize,
+    memory: MemoryManager,
+    disk: CACacheManager,
+    /// Policies of the entries on disk, keyed the way `disk` keys them.
+    spilled: Arc<Mutex<HashMap<String, CachePolicy>>>,
+}
+
+impl HybridManager {
+    /// Creates a manager that spills bodies of `spill_threshold` bytes or
+    /// more to `disk`.
+    pub fn new(spill_threshold: usize, disk: CACacheManager) -> Self {
+        HybridManager {
+            spill_threshold,
+            memory: MemoryManager::new(),
+            disk,
+            spilled: Default::default(),
+        }
+    }
+
+    /// Prefixes every key this manager uses with `namespace`.
+    pub fn with_namespace(mut self, namespace: impl Into<String>) -> Self {
+        let namespace = namespace.into();
+        self.memory = self.memory.with_namespace(namespace.clone());
+        self.disk = self.disk.with_namespace(namespace);
+        self
+    }
+
+    /// Policy of the entry spilled for `req`, if the disk still has it.
+    /// Anything the disk evicted on its own is dropped from the index.
+    async fn spilled_policy(&self, req: &Request) -> Result<Option<CachePolicy>, surf::Exception> {
+        let key = self.disk.key(req);
+        let policy = self.spilled.lock().unwrap().get(&key).cloned();
+        match policy {
+            Some(policy) if self.disk.contains(req).await? => Ok(Some(policy)),
+            Some(_) => {
+                self.forget(&key);
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn forget(&self, key: &str) {
+        self.spilled.lock().unwrap().remove(key);
+    }
+}
+
+#[async_trait]
+impl CacheManager for HybridManager {
+    async fn get(&self, req: &Request) -> Result<Option<CacheEntry>, surf::Exception> {
+        if let Some(entry) = self.memory.get(req).await? {
+            return Ok(Some(entry));
+        }
+        let key = self.disk.key(req);
+        if !self.spilled.lock().unwrap().contains_key(&key) {
+            return Ok(None);
+        }
+        let entry = self.disk.get(req).await?;
+        if entry.is_none() {
+            self.forget(&key);
+        }
+        Ok(entry)
+    }
+
+    async fn put(
+        &self,
+        req: &Request,
+        res: Response,
+        policy: CachePolicy,
+    ) -> Result<Response, surf::Exception> {
+        let (parts, mut body) = res.into_parts();
+        let mut bytes = Vec::new();
+        body.read_to_end(&mut bytes).await?;
+        let spill = bytes.len() >= self.spill_threshold;
+        let res = http::Response::from_parts(parts, Body::from(bytes));
+        // Whichever side the entry doesn't go to may still hold an older
+        // copy of it, from before its body changed size.
+        if spill {
+            self.memory.delete(req).await?;
+            let res = self.disk.put(req, res, policy.clone()).await?;
+            self.spilled
+                .lock()
+                .unwrap()
+                .insert(self.disk.key(req), policy);
+            Ok(res)
+        } else {
+            if self
+                .spilled
+                .lock()
+                .unwrap()
+                .remove(&self.disk.key(req))
+                .is_some()
+            {
+                self.disk.delete(req).await?;
+            }
+            self.memory.put(req, res, policy).await
+        }
+    }
+
+    async fn delete(&self, req: &Request) -> Result<(), surf::Exception> {
+        self.memory.delete(req).await?;
+        if self
+            .spilled
+            .lock()
+            .unwrap()
+            .remove(&self.disk.key(req))
+            .is_some()
+        {
+            self.disk.delete(req).await?;
+        }
+        Ok(())
+    }
+
+    async fn get_metadata(&self, req: &Request) -> Result<Option<CachePolicy>, surf::Exception> {
+        match self.memory.get_metadata(req).await? {
+            Some(policy) => Ok(Some(policy)),
+            None => self.spilled_policy(req).await,
+        }
+    }
+
+    async fn contains(&self, req: &Request) -> Result<bool, surf::Exception> {
+        Ok(self.memory.contains(req).await? || self.spilled_policy(req).await?.is_some())
+    }
+
+    async fn list(&self) -> Result<Vec<EntryInfo>, surf::Exception> {
+        let mut entries = self.memory.list().await?;
+        entries.extend(
+            self.spilled
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(key, policy)| EntryInfo {
+                    key: key.clone(),
+                    stored_at: policy.response_time(),
+                    fresh_until: policy.fresh_until(),
+                }),
+        );
+        Ok(entries)
+    }
+
+    async fn purge_expired(&self) -> Result<usize, surf::Exception> {
+        let now = clock::now();
+        let expired: Vec<String> = self
+            .spilled
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, policy)| policy.fresh_until() + policy.stale_grace() < now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        self.disk
+            .delete_matching(&|key| expired.iter().any(|expired| expired == key))
+            .await?;
+        {
+            let mut spilled = self.spilled.lock().unwrap();
+            for key in &expired {
+                spilled.remove(key);
+            }
+        }
+        Ok(self.memory.purge_expired().await? + expired.len())
+    }
+
+    async fn delete_matching(
+        &self,
+        predicate: &(dyn for<'k> Fn(&'k str) -> bool + Send + Sync),
+    ) -> Result<usize, surf::Exception> {
+        let deleted = self.disk.delete_matching(predicate).await?;
+        self.spilled
+            .lock()
+            .unwrap()
+            .retain(|key, _| !predicate(key));
+        Ok(self.memory.delete_matching(predicate).await? + deleted)
+    }
+
+    async fn entries(&self) -> Result<Vec<(String, CacheEntry)>, surf::Exception> {
+        let mut entries = self.memory.entries().await?;
+        let spilled: Vec<String> = self.spilled.lock().unwrap().keys().cloned().collect();
+        for (key, entry) in self.disk.entries().await? {
+            if spilled.contains(&key) {
+                entries.push((key, entry));
+            }
+        }
+        Ok(entries)
+    }
+
+    async fn migrate(&self) -> Result<usize, surf::Exception> {
+        // Entries on disk from older versions were never indexed by this
+        // process, so there's nothing of ours to drop there.
+        Ok(0)
+    }
+
+    async fn stats(&self, req: &Request) -> Result<Option<EntryStats>, surf::Exception> {
+        match self.memory.stats(req).await? {
+            Some(stats) => Ok(Some(stats)),
+            None => match self.spilled_policy(req).await? {
+                Some(_) => self.disk.stats(req).await,
+                None => Ok(None),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get(uri: &str) -> Request {
+        http::Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    fn response(body: &str) -> Response {
+        let mut res = http::Response::builder();
+        res.header("Cache-Control", "max-age=60");
+        res.body(Body::from(body.as_bytes().to_vec())).unwrap()
+    }
+
+    async fn body_string(res: Response) -> String {
+        let mut body = String::new();
+        res.into_body().read_to_string(&mut body).await.unwrap();
+        body
+    }
+
+    fn manager(dir: &tempfile::TempDir) -> (HybridManager, CACacheManager) {
+        let disk = CACacheManager::new(dir.path().to_str().unwrap()).with_max_key_len(40);
+        (HybridManager::new(10, disk.clone()), disk)
+    }
+
+    #[async_attributes::test]
+    async fn keeps_small_bodies_in_memory_and_large_ones_on_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let (hybrid, disk) = manager(&dir);
+        let small = get("http://example.com/small");
+        let large = get("http://example.com/large");
+        let policy = |req: &Request| CachePolicy::new(req, &response(""));
+
+        hybrid
+            .put(&small, response("tiny"), policy(&small))
+            .await
+            .unwrap();
+        hybrid
+            .put(&large, response("much too large"), policy(&large))
+            .await
+            .unwrap();
+
+        assert!(disk.get(&small).await.unwrap().is_none());
+        assert!(disk.get(&large).await.unwrap().is_some());
+        let entry = hybrid.get(&small).await.unwrap().unwrap();
+        assert_eq!(body_string(entry.response).await, "tiny");
+        let entry = hybrid.get(&large).await.unwrap().unwrap();
+        assert_eq!(body_string(entry.response).await, "much too large");
+    }
+
+    #[async_attributes::test]
+    async fn forgets_spilled_entries_the_disk_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let (hybrid, disk) = manager(&dir);
+        let large = get("http://example.com/large");
+        hybrid
+            .put(
+                &large,
+                response("much too large"),
+                CachePolicy::new(&large, &response("")),
+            )
+            .await
+            .unwrap();
+
+        disk.delete(&large).await.unwrap();
+        assert!(hybrid.get_metadata(&large).await.unwrap().is_none());
+        assert!(!hybrid.contains(&large).await.unwrap());
+        assert!(hybrid.list().await.unwrap().is_empty());
+    }
+
+    #[async_attributes::test]
+    async fn forgets_spilled_entries_the_disk_evicted() {
+        let dir = tempfile::tempdir().unwrap();
+        let disk = CACacheManager::new(dir.path().to_str().unwrap()).with_max_entries(1);
+        let hybrid = HybridManager::new(10, disk);
+        let first = get("http://example.com/first");
+        let second = get("http://example.com/second");
+        for req in &[&first, &second] {
+            std::thread::sleep(std::time::Duration::from_millis(2));
+            hybrid
+                .put(
+                    req,
+                    response("much too large"),
+                    CachePolicy::new(req, &response("")),
+                )
+                .await
+                .unwrap();
+        }
+
+        assert!(!hybrid.contains(&first).await.unwrap());
+        assert!(hybrid.get_metadata(&first).await.unwrap().is_none());
+        assert!(hybrid.get(&first).await.unwrap().is_none());
+        assert_eq!(hybrid.list().await.unwrap().len(), 1);
+        assert!(hybrid.contains(&second).await.unwrap());
+        let entry = hybrid.get(&second).await.unwrap().unwrap();
+        assert_eq!(body_string(entry.response).await, "much too large");
+    }
+
+    #[async_attributes::test]
+    async fn deletes_spilled_entries_by_their_disk_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let (hybrid, disk) = manager(&dir);
+        // Long enough for the disk to hash its key.
+        let large = get("http://example.com/a/very/long/path/indeed");
+        hybrid
+            .put(
+                &large,
+                response("much too large"),
+                CachePolicy::new(&large, &response("")),
+            )
+            .await
+            .unwrap();
+
+        let hashed = |key: &str| key.starts_with("sha256:");
+        assert_eq!(hybrid.delete_matching(&hashed).await.unwrap(), 1);
+        assert!(disk.get(&large).await.unwrap().is_none());
+        assert!(hybrid.get(&large).await.unwrap().is_none());
+        assert!(hybrid.list().await.unwrap().is_empty());
+    }
+}
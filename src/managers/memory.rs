@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use futures::io::AsyncReadExt;
+use http::HeaderMap;
+use surf::middleware::{Body, Request, Response};
+
+use super::req_key;
+use crate::{clock, CacheEntry, CacheManager, CachePolicy, EntryInfo, EntryStats, OriginLatency};
+
+/// Implements [`CacheManager`] by keeping entries in memory. Nothing is
+/// persisted, and clones share the same entries. Entries are swapped in
+/// whole under a lock, so `get`s never see a partially written one.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryManager {
+    entries: Arc<Mutex<HashMap<String, MemoryEntry>>>,
+    namespace: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct MemoryEntry {
+    status: http::StatusCode,
+    version: http::Version,
+    headers: HeaderMap,
+    body: Vec<u8>,
+    policy: CachePolicy,
+    stats: EntryStats,
+}
+
+impl MemoryManager {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Prefixes every key this manager uses with `namespace`.
+    pub fn with_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = Some(namespace.into());
+        self
+    }
+
+    pub(crate) fn key(&self, req: &Request) -> String {
+        req_key(self.namespace.as_deref(), req)
+    }
+}
+
+async fn to_entry(res: Response, policy: CachePolicy) -> Result<MemoryEntry, surf::Exception> {
+    let (parts, mut body) = res.into_parts();
+    let mut bytes = Vec::new();
+    body.read_to_end(&mut bytes).await?;
+    Ok(MemoryEntry {
+        stats: EntryStats {
+            origin_latency_ms: OriginLatency::millis(&parts.extensions),
+            body_len: Some(bytes.len() as u64),
+        },
+        status: parts.status,
+        version: parts.version,
+        headers: parts.headers,
+        body: bytes,
+        policy,
+    })
+}
+
+fn from_entry(entry: &MemoryEntry) -> Response {
+    let mut res = http::Response::builder();
+    res.status(entry.status);
+    res.version(entry.version);
+    let headers = res.headers_mut().expect("Couldn't get headers.");
+    *headers = entry.headers.clone();
+    if !entry.policy.is_head() {
+        headers.insert(http::header::CONTENT_LENGTH, entry.body.len().into());
+    }
+    res.body(Body::from(entry.body.clone())).unwrap()
+}
+
+#[async_trait]
+impl CacheManager for MemoryManager {
+    async fn get(&self, req: &Request) -> Result<Option<CacheEntry>, surf::Exception> {
+        Ok(self
+            .entries
+            .lock()
+            .unwrap()
+            .get(&self.key(req))
+            .map(|entry| CacheEntry {
+                response: from_entry(entry),
+                policy: entry.policy.clone(),
+                stored_at: entry.policy.response_time(),
+                namespace: self.namespace.clone(),
+            }))
+    }
+
+    async fn put(
+        &self,
+        req: &Request,
+        res: Response,
+        policy: CachePolicy,
+    ) -> Result<Response, surf::Exception> {
+        let entry = to_entry(res, policy).await?;
+        let res = from_entry(&entry);
+        self.entries.lock().unwrap().insert(self.key(req), entry);
+        Ok(res)
+    }
+
+    async fn delete(&self, req: &Request) -> Result<(), surf::Exception> {
+        self.entries.lock().unwrap().remove(&self.key(req));
+        Ok(())
+    }
+
+    async fn get_metadata(&self, req: &Request) -> Result<Option<CachePolicy>, surf::Exception> {
+        Ok(self
+            .entries
+            .lock()
+            .unwrap()
+            .get(&self.key(req))
+            .map(|entry| entry.policy.clone()))
+    }
+
+    async fn contains(&self, req: &Request) -> Result<bool, surf::Exception> {
+        Ok(self.entries.lock().unwrap().contains_key(&self.key(req)))
+    }
+
+    async fn list(&self) -> Result<Vec<EntryInfo>, surf::Exception> {
+        Ok(self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(key, entry)| EntryInfo {
+                key: key.clone(),
+                stored_at: entry.policy.response_time(),
+                fresh_until: entry.policy.fresh_until(),
+            })
+            .collect())
+    }
+
+    async fn purge_expired(&self) -> Result<usize, surf::Exception> {
+        let now = clock::now();
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|_, entry| entry.policy.fresh_until() + entry.policy.stale_grace() >= now);
+        Ok(before - entries.len())
+    }
+
+    async fn delete_matching(
+        &self,
+        predicate: &(dyn for<'k> Fn(&'k str) -> bool + Send + Sync),
+    ) -> Result<usize, surf::Exception> {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|key, _| !predicate(key));
+        Ok(before - entries.len())
+    }
+
+    async fn entries(&self) -> Result<Vec<(String, CacheEntry)>, surf::Exception> {
+        Ok(self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(key, entry)| {
+                let entry = CacheEntry {
+                    response: from_entry(entry),
+                    policy: entry.policy.clone(),
+                    stored_at: entry.policy.response_time(),
+                    namespace: self.namespace.clone(),
+                };
+                (key.clone(), entry)
+            })
+            .collect())
+    }
+
+    async fn migrate(&self) -> Result<usize, surf::Exception> {
+        // Nothing outlives the process, so nothing can be left over from an
+        // older version.
+        Ok(0)
+    }
+
+    async fn stats(&self, req: &Request) -> Result<Option<EntryStats>, surf::Exception> {
+        Ok(self
+            .entries
+            .lock()
+            .unwrap()
+            .get(&self.key(req))
+            .map(|entry| entry.stats))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn get(uri: &str) -> Request {
+        http::Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    fn response(cache_control: &str, body: &str) -> Response {
+        let mut res = http::Response::builder();
+        res.header("Cache-Control", cache_control);
+        res.body(Body::from(body.as_bytes().to_vec())).unwrap()
+    }
+
+    async fn put(manager: &MemoryManager, uri: &str, cache_control: &str) {
+        let req = get(uri);
+        let policy = CachePolicy::new(&req, &response(cache_control, ""));
+        manager
+            .put(&req, response(cache_control, uri), policy)
+            .await
+            .unwrap();
+    }
+
+    #[async_attributes::test]
+    async fn lists_when_each_entry_stops_being_fresh() {
+        clock::mock::freeze();
+        let manager = MemoryManager::new();
+        put(&manager, "http://example.com/1", "max-age=60").await;
+        put(&manager, "http://example.com/2", "max-age=120").await;
+
+        let mut entries = manager.list().await.unwrap();
+        entries.sort_by(|a, b| a.key.cmp(&b.key));
+        assert_eq!(
+            entries[0].fresh_until,
+            entries[0].stored_at + Duration::from_secs(60)
+        );
+        assert_eq!(
+            entries[1].fresh_until,
+            entries[0].fresh_until + Duration::from_secs(60)
+        );
+    }
+
+    #[async_attributes::test]
+    async fn purges_just_the_expired_entry() {
+        clock::mock::freeze();
+        let manager = MemoryManager::new();
+        put(&manager, "http://example.com/short", "max-age=10").await;
+        put(&manager, "http://example.com/long", "max-age=100").await;
+        clock::mock::advance(Duration::from_secs(50));
+
+        assert_eq!(manager.purge_expired().await.unwrap(), 1);
+        assert!(!manager
+            .contains(&get("http://example.com/short"))
+            .await
+            .unwrap());
+        assert!(manager
+            .contains(&get("http://example.com/long"))
+            .await
+            .unwrap());
+    }
+
+    #[async_attributes::test]
+    async fn contains_tracks_puts_and_deletes() {
+        let manager = MemoryManager::new();
+        let req = get("http://example.com/1");
+        assert!(!manager.contains(&req).await.unwrap());
+        put(&manager, "http://example.com/1", "max-age=60").await;
+        assert!(manager.contains(&req).await.unwrap());
+        manager.delete(&req).await.unwrap();
+        assert!(!manager.contains(&req).await.unwrap());
+    }
+
+    #[async_attributes::test]
+    async fn deletes_just_the_matching_keys() {
+        let manager = MemoryManager::new();
+        put(&manager, "http://example.com/api/1", "max-age=60").await;
+        put(&manager, "http://example.com/api/2", "max-age=60").await;
+        put(&manager, "http://example.com/static/1", "max-age=60").await;
+
+        let deleted = manager
+            .delete_matching(&|key: &str| key.starts_with("GET:http://example.com/api/"))
+            .await
+            .unwrap();
+        assert_eq!(deleted, 2);
+        let keys: Vec<_> = manager
+            .list()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.key)
+            .collect();
+        assert_eq!(keys, vec!["GET:http://example.com/static/1"]);
+    }
+}
@@ -0,0 +1,198 @@
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use surf::middleware::{Request, Response};
+
+use super::MemoryManager;
+use crate::{CacheEntry, CacheManager, CachePolicy, EntryInfo, EntryStats};
+
+/// A single call made against a [`MockManager`], along with the cache key it
+/// was made for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MockCall {
+    Get(String),
+    Put(String),
+    Delete(String),
+    /// A single `get_many` call, with the key of every request in it.
+    GetMany(Vec<String>),
+    /// A single `put_many` call, with the key of every request in it.
+    PutMany(Vec<String>),
+}
+
+/// In-memory [`CacheManager`] meant for tests. It records every call made to
+/// it and can be seeded with canned entries. Clones share the same entries
+/// and call log, so a test can keep a handle to inspect after handing a
+/// clone to a [`Cache`](crate::Cache).
+#[derive(Debug, Clone, Default)]
+pub struct MockManager {
+    inner: MemoryManager,
+    calls: Arc<Mutex<Vec<MockCall>>>,
+}
+
+impl MockManager {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Prefixes every key this manager uses with `namespace`.
+    pub fn with_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.inner = self.inner.with_namespace(namespace);
+        self
+    }
+
+    /// Every call made so far, in order.
+    pub fn calls(&self) -> Vec<MockCall> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// Forgets every recorded call. Stored entries are left alone.
+    pub fn clear_calls(&self) {
+        self.calls.lock().unwrap().clear();
+    }
+
+    /// Stores an entry for `req` without recording a call.
+    pub async fn seed(
+        &self,
+        req: &Request,
+        res: Response,
+        policy: CachePolicy,
+    ) -> Result<(), surf::Exception> {
+        self.inner.put(req, res, policy).await?;
+        Ok(())
+    }
+
+    fn record(&self, call: MockCall) {
+        self.calls.lock().unwrap().push(call);
+    }
+}
+
+#[async_trait]
+impl CacheManager for MockManager {
+    async fn get(&self, req: &Request) -> Result<Option<CacheEntry>, surf::Exception> {
+        self.record(MockCall::Get(self.inner.key(req)));
+        self.inner.get(req).await
+    }
+
+    async fn put(
+        &self,
+        req: &Request,
+        res: Response,
+        policy: CachePolicy,
+    ) -> Result<Response, surf::Exception> {
+        self.record(MockCall::Put(self.inner.key(req)));
+        self.inner.put(req, res, policy).await
+    }
+
+    async fn delete(&self, req: &Request) -> Result<(), surf::Exception> {
+        self.record(MockCall::Delete(self.inner.key(req)));
+        self.inner.delete(req).await
+    }
+
+    async fn contains(&self, req: &Request) -> Result<bool, surf::Exception> {
+        self.inner.contains(req).await
+    }
+
+    async fn list(&self) -> Result<Vec<EntryInfo>, surf::Exception> {
+        self.inner.list().await
+    }
+
+    async fn purge_expired(&self) -> Result<usize, surf::Exception> {
+        self.inner.purge_expired().await
+    }
+
+    async fn delete_matching(
+        &self,
+        predicate: &(dyn for<'k> Fn(&'k str) -> bool + Send + Sync),
+    ) -> Result<usize, surf::Exception> {
+        self.inner.delete_matching(predicate).await
+    }
+
+    async fn entries(&self) -> Result<Vec<(String, CacheEntry)>, surf::Exception> {
+        self.inner.entries().await
+    }
+
+    async fn migrate(&self) -> Result<usize, surf::Exception> {
+        self.inner.migrate().await
+    }
+
+    async fn stats(&self, req: &Request) -> Result<Option<EntryStats>, surf::Exception> {
+        self.inner.stats(req).await
+    }
+
+    async fn get_many(&self, reqs: &[Request]) -> Result<Vec<Option<CacheEntry>>, surf::Exception> {
+        self.record(MockCall::GetMany(
+            reqs.iter().map(|req| self.inner.key(req)).collect(),
+        ));
+        self.inner.get_many(reqs).await
+    }
+
+    async fn put_many(
+        &self,
+        entries: Vec<(Request, Response, CachePolicy)>,
+    ) -> Result<Vec<Response>, surf::Exception> {
+        self.record(MockCall::PutMany(
+            entries
+                .iter()
+                .map(|(req, _, _)| self.inner.key(req))
+                .collect(),
+        ));
+        self.inner.put_many(entries).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::io::AsyncReadExt;
+    use surf::middleware::Body;
+
+    use super::*;
+
+    fn get(uri: &str) -> Request {
+        http::Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    fn response(body: &str) -> Response {
+        http::Response::builder()
+            .header("Cache-Control", "max-age=60")
+            .body(Body::from(body.as_bytes().to_vec()))
+            .unwrap()
+    }
+
+    #[async_attributes::test]
+    async fn batches_are_recorded_as_one_call_each() {
+        let manager = MockManager::new();
+        let uris = [
+            "http://example.com/1",
+            "http://example.com/2",
+            "http://example.com/3",
+        ];
+        let entries = uris
+            .iter()
+            .map(|uri| {
+                let req = get(uri);
+                let policy = CachePolicy::new(&req, &response(""));
+                (req, response(uri), policy)
+            })
+            .collect();
+        manager.put_many(entries).await.unwrap();
+        let reqs: Vec<Request> = uris.iter().map(|uri| get(uri)).collect();
+        let found = manager.get_many(&reqs).await.unwrap();
+
+        let keys: Vec<String> = uris.iter().map(|uri| format!("GET:{}", uri)).collect();
+        assert_eq!(
+            manager.calls(),
+            vec![MockCall::PutMany(keys.clone()), MockCall::GetMany(keys)]
+        );
+        for (uri, entry) in uris.iter().zip(found) {
+            let mut body = String::new();
+            entry
+                .unwrap()
+                .response
+                .into_body()
+                .read_to_string(&mut body)
+                .await
+                .unwrap();
+            assert_eq!(&body, uri);
+        }
+    }
+}
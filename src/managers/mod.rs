@@ -0,0 +1,79 @@
+use sha2::{Digest, Sha256};
+use surf::middleware::Request;
+
+use crate::{BodyHash, EncodingVariant, Partition};
+
+mod bytestore;
+#[cfg(not(target_arch = "wasm32"))]
+mod cacache;
+#[cfg(not(target_arch = "wasm32"))]
+mod fs;
+#[cfg(not(target_arch = "wasm32"))]
+mod hybrid;
+mod memory;
+#[cfg(feature = "test-util")]
+mod mock;
+mod store;
+mod tiered;
+
+pub use self::bytestore::{ByteStore, ByteStoreManager};
+#[cfg(not(target_arch = "wasm32"))]
+pub use self::cacache::CACacheManager;
+#[cfg(not(target_arch = "wasm32"))]
+pub use self::fs::FsManager;
+#[cfg(not(target_arch = "wasm32"))]
+pub use self::hybrid::HybridManager;
+pub use self::memory::MemoryManager;
+#[cfg(feature = "test-util")]
+pub use self::mock::{MockCall, MockManager};
+pub use self::tiered::TieredManager;
+
+pub(crate) fn req_key(namespace: Option<&str>, req: &Request) -> String {
+    let mut key = String::new();
+    if let Some(namespace) = namespace {
+        key.push_str(namespace);
+        key.push(':');
+    }
+    if let Some(Partition(partition)) = req.extensions().get::<Partition>() {
+        key.push_str("partition=");
+        key.push_str(partition);
+        key.push(':');
+    }
+    key.push_str(&format!("{}:{}", req.method(), req.uri()));
+    if let Some(BodyHash(hash)) = req.extensions().get::<BodyHash>() {
+        key.push(':');
+        key.push_str(hash);
+    }
+    if let Some(EncodingVariant(encoding)) = req.extensions().get::<EncodingVariant>() {
+        key.push_str(":ae=");
+        key.push_str(encoding);
+    }
+    key
+}
+
+/// Like [`req_key`], but keys longer than `max_len` are swapped for the hex
+/// SHA-256 of the full key, still under `namespace`, to stay within backend
+/// key-length limits.
+///
+/// Hashed keys start with `sha256:` where readable ones start with a method
+/// or partition, so the two can't collide with each other, and collisions
+/// between hashed keys are as unlikely as SHA-256 collisions. They do hide
+/// the URL, so `delete_matching` predicates can't match them by prefix.
+pub(crate) fn bounded_req_key(
+    namespace: Option<&str>,
+    req: &Request,
+    max_len: Option<usize>,
+) -> String {
+    let key = req_key(namespace, req);
+    match max_len {
+        Some(max_len) if key.len() > max_len => {
+            let mut hasher = Sha256::new();
+            hasher.input(key.as_bytes());
+            match namespace {
+                Some(namespace) => format!("{}:sha256:{:x}", namespace, hasher.result()),
+                None => format!("sha256:{:x}", hasher.result()),
+            }
+        }
+        _ => key,
+    }
+}
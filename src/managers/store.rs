@@ -0,0 +1,343 @@
+//! The serialized form of a cache entry, shared by every manager that stores
+//! entries as bytes.
+
+use std::time::SystemTime;
+
+use futures::io::AsyncReadExt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use surf::middleware::{Body, Response};
+
+use crate::{CachePolicy, EntryStats, OriginLatency};
+
+/// Bumped whenever the layout of `Store` changes incompatibly. Entries
+/// written under any other version are treated as missing.
+pub(crate) const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct Store {
+    /// Always `SCHEMA_VERSION` when written. Entries from before versioning
+    /// was added read as `0`.
+    #[serde(default)]
+    pub(crate) schema: u32,
+    pub(crate) response: StoredResponse,
+    pub(crate) policy: CachePolicy,
+    pub(crate) stored_at: SystemTime,
+    pub(crate) fresh_until: SystemTime,
+    #[serde(default)]
+    pub(crate) origin_latency_ms: Option<u64>,
+    #[serde(default)]
+    pub(crate) body_len: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct StoredResponse {
+    body: Vec<u8>,
+    /// Every value of every header, in order.
+    headers: Vec<(String, Vec<StoredValue>)>,
+    status: u16,
+    version: String,
+    /// Storage-level compression applied to `body`, if any. This is
+    /// unrelated to the response's own `Content-Encoding`.
+    #[serde(default)]
+    codec: Option<String>,
+    /// Hex SHA-256 of `body`, as stored. Entries written before this was
+    /// added don't have one.
+    #[serde(default)]
+    integrity: Option<String>,
+}
+
+/// A header value, kept as text when it's UTF-8 so stored entries stay
+/// readable, and as raw bytes when it isn't. Entries written before values
+/// could be bytes read back as text.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub(crate) enum StoredValue {
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+impl StoredValue {
+    pub(crate) fn new(value: &http::HeaderValue) -> Self {
+        match String::from_utf8(value.as_bytes().to_vec()) {
+            Ok(text) => StoredValue::Text(text),
+            Err(e) => StoredValue::Bytes(e.into_bytes()),
+        }
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        match self {
+            StoredValue::Text(text) => text.as_bytes(),
+            StoredValue::Bytes(bytes) => bytes,
+        }
+    }
+}
+
+pub(crate) async fn to_store(res: Response, policy: CachePolicy) -> Result<Store, surf::Exception> {
+    let (parts, mut body) = res.into_parts();
+    let mut bytes = Vec::new();
+    body.read_to_end(&mut bytes).await?;
+    let body_len = bytes.len() as u64;
+    let mut headers = Vec::new();
+    for name in parts.headers.keys() {
+        let values = parts.headers.get_all(name).iter().map(StoredValue::new);
+        headers.push((name.as_str().to_string(), values.collect()));
+    }
+    let (body, codec) = compress(bytes, &policy)?;
+    Ok(Store {
+        schema: SCHEMA_VERSION,
+        response: StoredResponse {
+            integrity: Some(checksum(&body)),
+            body,
+            headers,
+            status: parts.status.as_u16(),
+            version: format!("{:?}", parts.version),
+            codec,
+        },
+        stored_at: policy.response_time(),
+        fresh_until: policy.fresh_until(),
+        origin_latency_ms: OriginLatency::millis(&parts.extensions),
+        body_len: Some(body_len),
+        policy,
+    })
+}
+
+#[cfg(feature = "compression")]
+fn compress(
+    body: Vec<u8>,
+    policy: &CachePolicy,
+) -> Result<(Vec<u8>, Option<String>), surf::Exception> {
+    // https://tools.ietf.org/html/rfc7234#section-5.2.2.4
+    //
+    // The "no-transform" response directive indicates that an intermediary
+    // (regardless of whether it implements a cache) MUST NOT transform the
+    // payload.
+    //
+    // Bodies that already have a `Content-Encoding` of their own are stored
+    // as they are too: compressing them again gains next to nothing. Either
+    // way, the response's own `Content-Encoding` header is left alone, since
+    // storage compression is undone before the body is served.
+    let is_encoded = policy
+        .response_headers()
+        .get("content-encoding")
+        .map(|encoding| !encoding.trim().eq_ignore_ascii_case("identity"))
+        .unwrap_or(false);
+    if policy.is_no_transform() || is_encoded {
+        Ok((body, None))
+    } else {
+        Ok((zstd::encode_all(&body[..], 0)?, Some("zstd".into())))
+    }
+}
+
+#[cfg(not(feature = "compression"))]
+fn compress(
+    body: Vec<u8>,
+    _policy: &CachePolicy,
+) -> Result<(Vec<u8>, Option<String>), surf::Exception> {
+    Ok((body, None))
+}
+
+fn decompress(response: &StoredResponse) -> Result<Vec<u8>, surf::Exception> {
+    match response.codec.as_deref() {
+        None => Ok(response.body.clone()),
+        #[cfg(feature = "compression")]
+        Some("zstd") => Ok(zstd::decode_all(&response.body[..])?),
+        Some(codec) => Err(format!("Unsupported cache codec: {}", codec).into()),
+    }
+}
+
+/// Rebuilds the stored response. Every call materializes a new body from the
+/// stored bytes, so responses from repeated `get`s can each be read in full
+/// independently of one another.
+pub(crate) fn from_store(store: &Store) -> Result<Response, surf::Exception> {
+    let mut res = http::Response::builder();
+    res.status(store.response.status);
+    res.version(match store.response.version.as_str() {
+        "HTTP/0.9" => http::Version::HTTP_09,
+        "HTTP/1.0" => http::Version::HTTP_10,
+        "HTTP/2.0" => http::Version::HTTP_2,
+        _ => http::Version::HTTP_11,
+    });
+    let headers = res.headers_mut().expect("Couldn't get headers.");
+    // Anything that can't be turned back into a header, e.g. because a buggy
+    // origin sent control characters, is left out rather than failing the
+    // whole response.
+    for (name, values) in store.response.headers.iter() {
+        let name = match http::header::HeaderName::from_bytes(name.as_bytes()) {
+            Ok(name) => name,
+            Err(_) => {
+                log::warn!("Skipping invalid stored header name {:?}", name);
+                continue;
+            }
+        };
+        for value in values {
+            match http::HeaderValue::from_bytes(value.as_bytes()) {
+                Ok(value) => {
+                    headers.append(&name, value);
+                }
+                Err(_) => log::warn!("Skipping invalid stored value for header {}", name),
+            }
+        }
+    }
+    let body = decompress(&store.response)?;
+    // Whatever `Content-Length` was stored describes the body as the origin
+    // sent it, which isn't necessarily what gets served back. Responses to
+    // `HEAD` are the exception: they never have a body, and their
+    // `Content-Length` is the length of the resource they describe.
+    if !store.policy.is_head() {
+        headers.insert(http::header::CONTENT_LENGTH, body.len().into());
+    }
+    Ok(res.body(Body::from(body)).unwrap())
+}
+
+fn checksum(body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.input(body);
+    format!("{:x}", hasher.result())
+}
+
+impl Store {
+    pub(crate) fn stats(&self) -> EntryStats {
+        EntryStats {
+            origin_latency_ms: self.origin_latency_ms,
+            body_len: self.body_len,
+        }
+    }
+
+    /// Whether the stored body still matches the checksum taken when it was
+    /// written. Catches corruption that still deserializes fine.
+    pub(crate) fn is_intact(&self) -> bool {
+        match &self.response.integrity {
+            Some(integrity) => *integrity == checksum(&self.response.body),
+            None => true,
+        }
+    }
+
+    pub(crate) fn to_bytes(&self) -> Result<Vec<u8>, surf::Exception> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    /// Reads a stored entry back, or `None` if it was written under a
+    /// different schema version.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Option<Self>, surf::Exception> {
+        if Store::is_current(bytes) {
+            Ok(Some(serde_json::from_slice(bytes)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Whether `bytes` hold an entry written under the current schema
+    /// version. Only the version is looked at, so this works on entries
+    /// whose layout can't be read anymore.
+    pub(crate) fn is_current(bytes: &[u8]) -> bool {
+        #[derive(Deserialize)]
+        struct Versioned {
+            #[serde(default)]
+            schema: u32,
+        }
+        serde_json::from_slice::<Versioned>(bytes)
+            .map(|versioned| versioned.schema == SCHEMA_VERSION)
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use surf::middleware::Request;
+
+    use super::*;
+
+    fn get() -> Request {
+        http::Request::get("http://example.com/")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn response(headers: &[(&str, &str)], body: &[u8]) -> Response {
+        let mut res = http::Response::builder();
+        for (name, value) in headers {
+            res.header(*name, *value);
+        }
+        res.body(Body::from(body.to_vec())).unwrap()
+    }
+
+    async fn store(headers: &[(&str, &str)], body: &[u8]) -> Store {
+        let policy = CachePolicy::new(&get(), &response(headers, b""));
+        to_store(response(headers, body), policy).await.unwrap()
+    }
+
+    #[async_attributes::test]
+    async fn stores_no_transform_bodies_verbatim() {
+        let body = "hello ".repeat(100);
+        let verbatim = store(
+            &[("Cache-Control", "max-age=60, no-transform")],
+            body.as_bytes(),
+        )
+        .await;
+        assert!(verbatim.response.codec.is_none());
+        assert_eq!(verbatim.response.body, body.as_bytes());
+
+        // Without it, the same body would have been compressed.
+        #[cfg(feature = "compression")]
+        {
+            let compressed = store(&[("Cache-Control", "max-age=60")], body.as_bytes()).await;
+            assert_eq!(compressed.response.codec.as_ref().unwrap(), "zstd");
+            assert_ne!(compressed.response.body, body.as_bytes());
+        }
+    }
+
+    #[async_attributes::test]
+    async fn content_length_matches_the_body_served() {
+        let stored = store(
+            &[("Cache-Control", "max-age=60"), ("Content-Length", "999")],
+            b"hello",
+        )
+        .await;
+        let res = from_store(&stored).unwrap();
+        assert_eq!(res.headers()["content-length"], "5");
+        assert_eq!(res.headers().get_all("content-length").iter().count(), 1);
+    }
+
+    #[async_attributes::test]
+    async fn head_entries_keep_the_resources_content_length() {
+        let head = http::Request::head("http://example.com/")
+            .body(Body::empty())
+            .unwrap();
+        let headers = [("Cache-Control", "max-age=60"), ("Content-Length", "1234")];
+        let policy = CachePolicy::new(&head, &response(&headers, b""));
+        let stored = to_store(response(&headers, b""), policy).await.unwrap();
+
+        let res = from_store(&stored).unwrap();
+        assert_eq!(res.headers()["content-length"], "1234");
+        let mut body = Vec::new();
+        res.into_body().read_to_end(&mut body).await.unwrap();
+        assert!(body.is_empty());
+    }
+
+    #[async_attributes::test]
+    async fn gzipped_bodies_are_stored_and_served_untouched() {
+        // Not a real gzip stream, but the cache has no business looking.
+        let gzipped: Vec<u8> = [0x1f, 0x8b, 0x08, 0x00]
+            .iter()
+            .cloned()
+            .chain(std::iter::repeat_n(b'z', 600))
+            .collect();
+        let stored = store(
+            &[
+                ("Cache-Control", "max-age=60"),
+                ("Content-Encoding", "gzip"),
+            ],
+            &gzipped,
+        )
+        .await;
+        assert!(stored.response.codec.is_none());
+        assert_eq!(stored.response.body, gzipped);
+
+        let res = from_store(&stored).unwrap();
+        assert_eq!(res.headers()["content-encoding"], "gzip");
+        let mut body = Vec::new();
+        res.into_body().read_to_end(&mut body).await.unwrap();
+        assert_eq!(body, gzipped);
+    }
+}
@@ -0,0 +1,153 @@
+use async_trait::async_trait;
+use surf::middleware::{Request, Response};
+
+use crate::{CacheEntry, CacheManager, CachePolicy, EntryInfo, EntryStats};
+
+/// Layers a fast cache, like a [`MemoryManager`](crate::MemoryManager), in
+/// front of a slower persistent one, like a
+/// [`CACacheManager`](crate::CACacheManager).
+///
+/// Lookups check `l1` first and fall back to `l2`, copying anything found
+/// there into `l1`. Writes and deletions go to both.
+///
+/// Each layer is only as atomic as its own manager. The two are updated one
+/// after the other, so while a `put` is underway `l1` may briefly still
+/// serve the old entry after `l2` already has the new one.
+#[derive(Debug, Clone)]
+pub struct TieredManager<A: CacheManager, B: CacheManager> {
+    pub l1: A,
+    pub l2: B,
+}
+
+impl<A: CacheManager, B: CacheManager> TieredManager<A, B> {
+    pub fn new(l1: A, l2: B) -> Self {
+        TieredManager { l1, l2 }
+    }
+}
+
+#[async_trait]
+impl<A, B> CacheManager for TieredManager<A, B>
+where
+    A: CacheManager + Send + Sync,
+    B: CacheManager + Send + Sync,
+{
+    async fn get(&self, req: &Request) -> Result<Option<CacheEntry>, surf::Exception> {
+        if let Some(entry) = self.l1.get(req).await? {
+            return Ok(Some(entry));
+        }
+        match self.l2.get(req).await? {
+            Some(entry) => {
+                let response = self
+                    .l1
+                    .put(req, entry.response, entry.policy.clone())
+                    .await?;
+                Ok(Some(CacheEntry { response, ..entry }))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn put(
+        &self,
+        req: &Request,
+        res: Response,
+        policy: CachePolicy,
+    ) -> Result<Response, surf::Exception> {
+        let res = self.l2.put(req, res, policy.clone()).await?;
+        self.l1.put(req, res, policy).await
+    }
+
+    async fn delete(&self, req: &Request) -> Result<(), surf::Exception> {
+        self.l1.delete(req).await?;
+        self.l2.delete(req).await
+    }
+
+    async fn get_metadata(&self, req: &Request) -> Result<Option<CachePolicy>, surf::Exception> {
+        match self.l1.get_metadata(req).await? {
+            Some(policy) => Ok(Some(policy)),
+            None => self.l2.get_metadata(req).await,
+        }
+    }
+
+    async fn contains(&self, req: &Request) -> Result<bool, surf::Exception> {
+        Ok(self.l1.contains(req).await? || self.l2.contains(req).await?)
+    }
+
+    async fn list(&self) -> Result<Vec<EntryInfo>, surf::Exception> {
+        self.l2.list().await
+    }
+
+    async fn purge_expired(&self) -> Result<usize, surf::Exception> {
+        self.l1.purge_expired().await?;
+        self.l2.purge_expired().await
+    }
+
+    async fn delete_matching(
+        &self,
+        predicate: &(dyn for<'k> Fn(&'k str) -> bool + Send + Sync),
+    ) -> Result<usize, surf::Exception> {
+        self.l1.delete_matching(predicate).await?;
+        self.l2.delete_matching(predicate).await
+    }
+
+    async fn entries(&self) -> Result<Vec<(String, CacheEntry)>, surf::Exception> {
+        self.l2.entries().await
+    }
+
+    async fn migrate(&self) -> Result<usize, surf::Exception> {
+        Ok(self.l1.migrate().await? + self.l2.migrate().await?)
+    }
+
+    async fn stats(&self, req: &Request) -> Result<Option<EntryStats>, surf::Exception> {
+        self.l2.stats(req).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::io::AsyncReadExt;
+    use surf::middleware::Body;
+
+    use super::*;
+    use crate::MemoryManager;
+
+    fn get(uri: &str) -> Request {
+        http::Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    fn response(body: &str) -> Response {
+        http::Response::builder()
+            .header("Cache-Control", "max-age=60")
+            .body(Body::from(body.as_bytes().to_vec()))
+            .unwrap()
+    }
+
+    #[async_attributes::test]
+    async fn promotes_l2_hits_into_l1() {
+        let manager = TieredManager::new(MemoryManager::new(), MemoryManager::new());
+        let req = get("http://example.com/1");
+        let policy = CachePolicy::new(&req, &response(""));
+        manager.l2.put(&req, response("one"), policy).await.unwrap();
+        assert!(manager.l1.get(&req).await.unwrap().is_none());
+
+        let entry = manager.get(&req).await.unwrap().unwrap();
+        let mut body = Vec::new();
+        entry
+            .response
+            .into_body()
+            .read_to_end(&mut body)
+            .await
+            .unwrap();
+        assert_eq!(body, b"one");
+
+        let promoted = manager.l1.get(&req).await.unwrap().unwrap();
+        let mut body = Vec::new();
+        promoted
+            .response
+            .into_body()
+            .read_to_end(&mut body)
+            .await
+            .unwrap();
+        assert_eq!(body, b"one");
+    }
+}
@@ -0,0 +1,155 @@
+use http::Uri;
+
+/// Rewrites `uri` into a canonical form so that semantically-equivalent URLs
+/// compare equal. Falls back to the original URI if the result doesn't parse.
+pub(crate) fn normalize_uri(uri: &Uri) -> Uri {
+    let scheme = uri.scheme_str().map(|s| s.to_lowercase());
+    let mut normalized = String::new();
+    if let Some(scheme) = &scheme {
+        normalized.push_str(scheme);
+        normalized.push_str("://");
+    }
+    if let Some(host) = uri.host() {
+        normalized.push_str(&host.trim_end_matches('.').to_lowercase());
+    }
+    let port = match (scheme.as_deref(), uri.port_u16()) {
+        (Some("http"), Some(80)) | (Some("https"), Some(443)) => None,
+        (_, port) => port,
+    };
+    if let Some(port) = port {
+        normalized.push_str(&format!(":{}", port));
+    }
+    normalized.push_str(&normalize_percent_encoding(uri.path()));
+    if let Some(query) = uri.query() {
+        let query = normalize_query(query);
+        if !query.is_empty() {
+            normalized.push('?');
+            normalized.push_str(&query);
+        }
+    }
+    normalized.parse().unwrap_or_else(|_| uri.clone())
+}
+
+/// Rewrites a query string so that requests sending the same parameters
+/// produce the same string, whatever order they're in:
+///
+/// 1. The query is split on `&`, and empty parameters are dropped.
+/// 2. Each parameter is split into a name and a value at its first `=`. A
+///    parameter without one has an empty value, so `a` and `a=` are the
+///    same.
+/// 3. Percent-encodings in names and values are uppercased.
+/// 4. Parameters are sorted by name, then by value, byte-wise. Repeated
+///    parameters are all kept, since origins may count them, but their
+///    order no longer matters.
+/// 5. They're joined back up as `name=value` pairs separated by `&`.
+fn normalize_query(query: &str) -> String {
+    let mut params: Vec<(String, String)> = query
+        .split('&')
+        .filter(|param| !param.is_empty())
+        .map(|param| {
+            let mut parts = param.splitn(2, '=');
+            let name = normalize_percent_encoding(parts.next().unwrap_or(""));
+            let value = normalize_percent_encoding(parts.next().unwrap_or(""));
+            (name, value)
+        })
+        .collect();
+    params.sort();
+    params
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Rewrites an `Accept-Encoding` value so that requests accepting the same
+/// codings produce the same string, regardless of order, case, or
+/// whitespace. Codings refused with `q=0` are dropped, and quality values
+/// are otherwise ignored: they only express a preference among codings the
+/// client accepts either way.
+pub(crate) fn normalize_accept_encoding(value: &str) -> String {
+    let mut codings: Vec<String> = value
+        .split(',')
+        .filter_map(|coding| {
+            let mut parts = coding.split(';');
+            let name = parts.next().unwrap_or("").trim().to_lowercase();
+            let refused = parts.any(|param| {
+                let param = param.trim().to_lowercase();
+                param.starts_with("q=")
+                    && param[2..]
+                        .trim()
+                        .parse::<f32>()
+                        .map(|q| q == 0.0)
+                        .unwrap_or(false)
+            });
+            if name.is_empty() || refused {
+                None
+            } else {
+                Some(name)
+            }
+        })
+        .collect();
+    codings.sort();
+    codings.dedup();
+    codings.join(",")
+}
+
+fn normalize_percent_encoding(s: &str) -> String {
+    let mut normalized = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        normalized.push(c);
+        if c == '%' {
+            for hex in chars.by_ref().take(2) {
+                normalized.push(hex.to_ascii_uppercase());
+            }
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalized(uri: &str) -> String {
+        normalize_uri(&uri.parse().unwrap()).to_string()
+    }
+
+    #[test]
+    fn sorts_reordered_parameters() {
+        assert_eq!(
+            normalized("http://example.com/?b=2&a=1&c=3"),
+            normalized("http://example.com/?c=3&a=1&b=2")
+        );
+        assert_eq!(
+            normalized("http://example.com/?b=2&a=1"),
+            "http://example.com/?a=1&b=2"
+        );
+    }
+
+    #[test]
+    fn keeps_repeated_parameters_sorted_by_value() {
+        assert_eq!(
+            normalized("http://example.com/?a=2&b=1&a=1"),
+            "http://example.com/?a=1&a=2&b=1"
+        );
+        // Repeats aren't collapsed, so these stay apart.
+        assert_ne!(
+            normalized("http://example.com/?a=1&a=1"),
+            normalized("http://example.com/?a=1")
+        );
+    }
+
+    #[test]
+    fn treats_empty_values_alike() {
+        assert_eq!(
+            normalized("http://example.com/?b&a="),
+            "http://example.com/?a=&b="
+        );
+        assert_eq!(
+            normalized("http://example.com/?&&a=1&"),
+            "http://example.com/?a=1"
+        );
+        assert_eq!(normalized("http://example.com/?&"), "http://example.com/");
+    }
+}
@@ -0,0 +1,750 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Duration, SystemTime};
+
+use http::HeaderMap;
+use serde::{Deserialize, Serialize};
+use surf::middleware::{Request, Response};
+
+use crate::clock;
+use crate::normalize::normalize_accept_encoding;
+
+/// Settings that change how a [`CachePolicy`] interprets a response.
+#[derive(Debug, Clone, Copy)]
+pub struct CacheOptions {
+    /// If `true`, the cache is shared between users (e.g. a proxy), and must
+    /// not store responses that are private to one of them. Defaults to
+    /// `false`, a private cache.
+    pub shared: bool,
+    /// Freshness lifetime to assume for responses that don't specify one
+    /// themselves, as if they had been sent with `max-age`. Responses marked
+    /// `no-store` or `no-cache` are never affected. Defaults to `None`.
+    pub default_ttl: Option<Duration>,
+    /// Fraction of the time since its `Last-Modified` date that a response
+    /// is considered fresh for, if it specifies no lifetime of its own and
+    /// no `default_ttl` is set. Defaults to `0.1`, the figure RFC 7234
+    /// suggests.
+    pub cache_heuristic: f32,
+    /// If `true`, responses that carry the old IE-only `pre-check` and
+    /// `post-check` directives have their `no-cache`, `no-store` and
+    /// `must-revalidate` directives, and their `Pragma` and `Expires`
+    /// headers, ignored, since they're almost always there out of habit
+    /// rather than intent. Defaults to `false`.
+    pub ignore_cargo_cult: bool,
+    /// If `true`, the cache acts as a surrogate, like a CDN edge, and a
+    /// response's `Surrogate-Control` directives, when it has any, are used
+    /// in place of its `Cache-Control` ones to decide whether and how long
+    /// to store it. Directives targeted at a specific surrogate are ignored.
+    /// Defaults to `false`.
+    pub surrogate: bool,
+}
+
+impl Default for CacheOptions {
+    fn default() -> Self {
+        CacheOptions {
+            shared: false,
+            default_ttl: None,
+            cache_heuristic: 0.1,
+            ignore_cargo_cult: false,
+            surrogate: false,
+        }
+    }
+}
+
+/// Everything the cache needs to know about a stored request/response pair
+/// in order to make freshness and revalidation decisions, without having to
+/// look at the response body.
+///
+/// The cache attaches the policy to the extensions of every response it
+/// serves from its store or stores on the way through, so callers can
+/// retrieve it with `res.ext::<CachePolicy>()`. Responses that were neither
+/// served from nor stored in the cache don't have one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachePolicy {
+    method: String,
+    uri: String,
+    status: u16,
+    // Ordered maps, so identical policies always serialize to identical
+    // bytes. Policies for the same response only come out identical when
+    // built at the same instant, though: `response_time` is when the policy
+    // was made, unless the response says otherwise, and a stored entry's
+    // `stored_at` is likewise when it was stored.
+    req_headers: BTreeMap<String, String>,
+    res_headers: BTreeMap<String, String>,
+    reqcc: BTreeMap<String, Option<String>>,
+    rescc: BTreeMap<String, Option<String>>,
+    shared: bool,
+    default_ttl: Option<Duration>,
+    #[serde(default)]
+    cache_heuristic: f32,
+    #[serde(default)]
+    ttl_override: Option<Duration>,
+    // Normalized `Accept-Encoding`s a separate variant of the response has
+    // been stored for, if it varies on `Accept-Encoding`.
+    #[serde(default)]
+    encodings: BTreeSet<String>,
+    response_time: SystemTime,
+}
+
+impl CachePolicy {
+    pub fn new(req: &Request, res: &Response) -> Self {
+        CachePolicy::new_options(req, res, CacheOptions::default())
+    }
+
+    pub fn new_options(req: &Request, res: &Response, options: CacheOptions) -> Self {
+        let req_headers = header_map(req.headers());
+        let mut res_headers = header_map(res.headers());
+        let mut rescc = parse_cache_control(res_headers.get("cache-control"));
+        if options.ignore_cargo_cult
+            && rescc.contains_key("pre-check")
+            && rescc.contains_key("post-check")
+        {
+            for directive in &[
+                "pre-check",
+                "post-check",
+                "no-cache",
+                "no-store",
+                "must-revalidate",
+            ] {
+                rescc.remove(*directive);
+            }
+            res_headers.remove("expires");
+            res_headers.remove("pragma");
+        }
+        if options.surrogate {
+            if let Some(surrogate_control) = res_headers.get("surrogate-control") {
+                rescc = parse_cache_control(Some(surrogate_control))
+                    .into_iter()
+                    .filter(|(_, value)| value.as_ref().is_none_or(|value| !value.contains(';')))
+                    .collect();
+            }
+        }
+        CachePolicy {
+            method: req.method().to_string(),
+            uri: req.uri().to_string(),
+            status: res.status().as_u16(),
+            reqcc: parse_cache_control(req_headers.get("cache-control")),
+            rescc,
+            req_headers,
+            res_headers,
+            shared: options.shared,
+            default_ttl: options.default_ttl,
+            cache_heuristic: options.cache_heuristic,
+            ttl_override: None,
+            encodings: BTreeSet::new(),
+            response_time: clock::now(),
+        }
+    }
+
+    /// Replaces whatever freshness lifetime the response specified with
+    /// `ttl`, e.g. for error responses the cache was configured to keep
+    /// briefly even though they didn't ask to be cached.
+    pub(crate) fn with_ttl_override(mut self, ttl: Duration) -> Self {
+        self.ttl_override = Some(ttl);
+        self
+    }
+
+    /// Records the normalized `Accept-Encoding`s that separate variants of
+    /// the response have been stored for, so they can all be found again
+    /// when it's invalidated.
+    pub(crate) fn with_encodings(mut self, encodings: BTreeSet<String>) -> Self {
+        self.encodings = encodings;
+        self
+    }
+
+    /// Normalized `Accept-Encoding`s that separate variants of the response
+    /// have been stored for.
+    pub(crate) fn encodings(&self) -> &BTreeSet<String> {
+        &self.encodings
+    }
+
+    /// Whether the response is allowed to be stored at all.
+    pub fn is_storable(&self) -> bool {
+        !self.reqcc.contains_key("no-store")
+            && !self.rescc.contains_key("no-store")
+            // https://tools.ietf.org/html/rfc7234#section-5.2.2.6
+            //
+            // If the private response directive specifies one or more
+            // field-names, this requirement is limited to the field-values
+            // associated with the listed response header fields.
+            && (!self.shared
+                || !self.rescc.contains_key("private")
+                || field_names(self.rescc.get("private")).is_some())
+            // https://tools.ietf.org/html/rfc7234#section-3.2
+            //
+            // A shared cache MUST NOT use a cached response to a request with
+            // an Authorization header field to satisfy any subsequent request
+            // unless a cache directive that allows such responses to be stored
+            // is present in the response.
+            && (!self.shared
+                || !self.req_headers.contains_key("authorization")
+                || self.allows_storing_authenticated())
+            // https://tools.ietf.org/html/rfc7234#section-4.1
+            //
+            // A Vary header field-value of "*" always fails to match.
+            && !self.varies_on_everything()
+    }
+
+    /// Whether `req` sends the same values as the stored request did for
+    /// every header the response `Vary`s on.
+    pub fn vary_matches(&self, req: &Request) -> bool {
+        // https://tools.ietf.org/html/rfc7234#section-4.1
+        //
+        // When a cache receives a request that can be satisfied by a stored
+        // response that has a Vary header field, it MUST NOT use that
+        // response unless all of the selecting header fields nominated by
+        // the Vary header field match in both the original request (i.e.,
+        // that associated with the stored response), and the presented
+        // request.
+        let vary = match self.res_headers.get("vary") {
+            Some(vary) => vary,
+            None => return true,
+        };
+        let req_headers = header_map(req.headers());
+        vary.split(',')
+            .map(|name| name.trim().to_lowercase())
+            .filter(|name| !name.is_empty())
+            .all(|name| {
+                let normalize = |value: &String| {
+                    if name == "accept-encoding" {
+                        normalize_accept_encoding(value)
+                    } else {
+                        value.trim().to_string()
+                    }
+                };
+                self.req_headers.get(&name).map(normalize) == req_headers.get(&name).map(normalize)
+            })
+    }
+
+    /// Whether the response's `Vary` names `Accept-Encoding`, so each
+    /// encoding of it needs an entry of its own.
+    pub(crate) fn varies_on_encoding(&self) -> bool {
+        self.res_headers
+            .get("vary")
+            .map(|vary| {
+                vary.split(',')
+                    .any(|name| name.trim().eq_ignore_ascii_case("accept-encoding"))
+            })
+            .unwrap_or(false)
+    }
+
+    fn varies_on_everything(&self) -> bool {
+        self.res_headers
+            .get("vary")
+            .map(|vary| vary.split(',').any(|name| name.trim() == "*"))
+            .unwrap_or(false)
+    }
+
+    fn allows_storing_authenticated(&self) -> bool {
+        self.rescc.contains_key("must-revalidate")
+            || self.rescc.contains_key("public")
+            || self.rescc.contains_key("s-maxage")
+    }
+
+    /// Whether the stored response has to be revalidated with the origin
+    /// every time it's used, no matter how fresh it is.
+    pub fn requires_revalidation(&self) -> bool {
+        // https://tools.ietf.org/html/rfc7234#section-5.2.2.2
+        //
+        // The "no-cache" response directive indicates that the response MUST
+        // NOT be used to satisfy a subsequent request without successful
+        // validation on the origin server.
+        self.rescc.contains_key("no-cache") && field_names(self.rescc.get("no-cache")).is_none()
+    }
+
+    /// Whether the stored response, once stale, may only be used after it's
+    /// been successfully revalidated, even if the origin can't be reached.
+    pub fn must_revalidate(&self) -> bool {
+        // https://tools.ietf.org/html/rfc7234#section-5.2.2.1
+        //
+        // The "must-revalidate" response directive indicates that once it
+        // has become stale, a cache MUST NOT use the response to satisfy
+        // subsequent requests without successful validation on the origin
+        // server.
+        //
+        // https://tools.ietf.org/html/rfc7234#section-5.2.2.7
+        //
+        // The "proxy-revalidate" response directive has the same meaning as
+        // the must-revalidate response directive, except that it does not
+        // apply to private caches.
+        self.rescc.contains_key("must-revalidate")
+            || (self.shared && self.rescc.contains_key("proxy-revalidate"))
+    }
+
+    /// Lowercase names of the response headers that mustn't be stored along
+    /// with the rest of the response: those a `no-cache` directive lists,
+    /// and, in a shared cache, those a `private` one does.
+    pub fn unstorable_headers(&self) -> Vec<String> {
+        // https://tools.ietf.org/html/rfc7234#section-5.2.2.2
+        //
+        // If the no-cache response directive specifies one or more
+        // field-names, then a cache MAY use the response to satisfy a
+        // subsequent request, subject to any other restrictions on caching.
+        // However, any header fields in the response that have the
+        // field-name(s) listed MUST NOT be sent in the response to a
+        // subsequent request without successful revalidation with the
+        // origin server.
+        let mut names = field_names(self.rescc.get("no-cache")).unwrap_or_default();
+        if self.shared {
+            names.extend(field_names(self.rescc.get("private")).unwrap_or_default());
+        }
+        names
+    }
+
+    /// Age of the response as of `now`: however old it already was when it
+    /// arrived, according to its `Age` and `Date` headers, plus how long it's
+    /// been sitting in the cache since.
+    pub fn age(&self, now: SystemTime) -> Duration {
+        // https://tools.ietf.org/html/rfc7234#section-4.2.3
+        //
+        // The Age header field conveys the sender's estimate of the amount of
+        // time since the response was generated or successfully validated at
+        // the origin server.
+        let age_value = self
+            .res_headers
+            .get("age")
+            .and_then(|age| age.trim().parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or_else(|| Duration::from_secs(0));
+        // A `Date` in the future, from a skewed origin clock, would make
+        // the apparent age negative, so it gets clamped to zero instead.
+        let apparent_age = self
+            .response_time
+            .duration_since(self.date())
+            .unwrap_or_else(|_| Duration::from_secs(0));
+        let resident_time = now
+            .duration_since(self.response_time)
+            .unwrap_or_else(|_| Duration::from_secs(0));
+        apparent_age.max(age_value) + resident_time
+    }
+
+    /// Total amount of time the response is considered fresh for, starting
+    /// from when it was generated.
+    pub fn max_age(&self) -> Duration {
+        if let Some(ttl) = self.ttl_override {
+            return ttl;
+        }
+        // https://tools.ietf.org/html/rfc7234#section-4.2.1
+        //
+        // If the max-age response directive is present, use its value, or
+        // if the Expires response header field is present, use its value
+        // minus the value of the Date response header field.
+        if self.shared {
+            // https://tools.ietf.org/html/rfc7234#section-5.2.2.9
+            //
+            // For a shared cache, the maximum age specified by this directive
+            // overrides the maximum age specified by either the max-age
+            // directive or the Expires header field.
+            if let Some(s_maxage) = self.rescc.get("s-maxage") {
+                return s_maxage
+                    .as_ref()
+                    .and_then(|v| v.parse().ok())
+                    .map(Duration::from_secs)
+                    .unwrap_or_else(|| Duration::from_secs(0));
+            }
+        }
+        if let Some(max_age) = self.rescc.get("max-age") {
+            return max_age
+                .as_ref()
+                .and_then(|v| v.parse().ok())
+                .map(Duration::from_secs)
+                .unwrap_or_else(|| Duration::from_secs(0));
+        }
+        if let Some(expires) = self.res_headers.get("expires") {
+            return match httpdate::parse_http_date(expires) {
+                // An invalid date, like "0", means the response has already
+                // expired.
+                Err(_) => Duration::from_secs(0),
+                Ok(expires) => expires
+                    .duration_since(self.date())
+                    .unwrap_or_else(|_| Duration::from_secs(0)),
+            };
+        }
+        if let Some(ttl) = self.default_ttl {
+            if !self.rescc.contains_key("no-cache") {
+                return ttl;
+            }
+        }
+        // https://tools.ietf.org/html/rfc7234#section-4.2.2
+        //
+        // If the response has a Last-Modified header field, caches are
+        // encouraged to use a heuristic expiration value that is no more
+        // than some fraction of the interval since that time. A typical
+        // setting of this fraction might be 10%.
+        self.res_headers
+            .get("last-modified")
+            .and_then(|last_modified| httpdate::parse_http_date(last_modified).ok())
+            .and_then(|last_modified| self.date().duration_since(last_modified).ok())
+            .map(|since| since.mul_f32(self.cache_heuristic))
+            .unwrap_or_else(|| Duration::from_secs(0))
+    }
+
+    /// How much longer the response will stay fresh as of `now`.
+    pub fn time_to_live(&self, now: SystemTime) -> Duration {
+        self.max_age()
+            .checked_sub(self.age(now))
+            .unwrap_or_else(|| Duration::from_secs(0))
+    }
+
+    /// Point in time at which the response stops being fresh.
+    pub fn fresh_until(&self) -> SystemTime {
+        self.response_time + self.time_to_live(self.response_time)
+    }
+
+    /// How long past its freshness lifetime the response may still be served,
+    /// per its `stale-while-revalidate` or `stale-if-error` directives.
+    pub fn stale_grace(&self) -> Duration {
+        ["stale-while-revalidate", "stale-if-error"]
+            .iter()
+            .filter_map(|directive| self.rescc.get(*directive))
+            .filter_map(|secs| secs.as_ref().and_then(|secs| secs.parse().ok()))
+            .max()
+            .map(Duration::from_secs)
+            .unwrap_or_else(|| Duration::from_secs(0))
+    }
+
+    /// Whether the response is past its freshness lifetime as of `now`.
+    ///
+    /// A response is stale the moment its age reaches its lifetime, not just
+    /// after, so one with `max-age=0` is stale as soon as it's stored and
+    /// always gets revalidated before being reused.
+    pub fn is_stale(&self, now: SystemTime) -> bool {
+        self.max_age() <= self.age(now)
+    }
+
+    /// Value of the response's `Date` header, or the time it was received if
+    /// it doesn't have a usable one.
+    fn date(&self) -> SystemTime {
+        self.res_headers
+            .get("date")
+            .and_then(|date| httpdate::parse_http_date(date).ok())
+            .unwrap_or(self.response_time)
+    }
+
+    /// Whether the response forbids any transformation of its payload.
+    pub fn is_no_transform(&self) -> bool {
+        self.rescc.contains_key("no-transform")
+    }
+
+    /// Method of the request the response was stored for.
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    /// Whether the stored response answered a `HEAD` request, and so has no
+    /// body, whatever its `Content-Length` says.
+    pub fn is_head(&self) -> bool {
+        self.method == "HEAD"
+    }
+
+    /// Status code of the stored response.
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// Headers of the stored response, keyed by lowercase header name.
+    pub fn response_headers(&self) -> &BTreeMap<String, String> {
+        &self.res_headers
+    }
+
+    /// Headers of the request the response was stored for, keyed by
+    /// lowercase header name.
+    pub fn request_headers(&self) -> &BTreeMap<String, String> {
+        &self.req_headers
+    }
+
+    /// Local time at which the response was received.
+    pub fn response_time(&self) -> SystemTime {
+        self.response_time
+    }
+}
+
+/// Cache directives in a request's `headers`, keyed by lowercase name.
+pub(crate) fn request_directives(headers: &HeaderMap) -> BTreeMap<String, Option<String>> {
+    let headers = header_map(headers);
+    let mut cc = parse_cache_control(headers.get("cache-control"));
+    // https://tools.ietf.org/html/rfc7234#section-5.4
+    //
+    // When the Cache-Control header field is not present in a request,
+    // caches MUST consider the no-cache request pragma-directive as having
+    // the same effect as if "Cache-Control: no-cache" were present.
+    if !headers.contains_key("cache-control") {
+        let pragma = parse_cache_control(headers.get("pragma"));
+        if pragma.contains_key("no-cache") {
+            cc.insert("no-cache".to_string(), None);
+        }
+    }
+    cc
+}
+
+/// The field-names listed by a `no-cache="..."` or `private="..."`
+/// directive, or `None` if it's absent or doesn't list any.
+fn field_names(directive: Option<&Option<String>>) -> Option<Vec<String>> {
+    let names: Vec<String> = directive?
+        .as_ref()?
+        .split(',')
+        .map(|name| name.trim().to_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect();
+    if names.is_empty() {
+        None
+    } else {
+        Some(names)
+    }
+}
+
+/// Splits a header into its comma-separated parts, leaving alone any
+/// commas inside quoted strings, like `private="Set-Cookie, Set-Cookie2"`.
+fn split_quoted(header: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut quoted = false;
+    let mut start = 0;
+    for (i, c) in header.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                parts.push(&header[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&header[start..]);
+    parts
+}
+
+fn parse_cache_control(header: Option<&String>) -> BTreeMap<String, Option<String>> {
+    let mut cc = BTreeMap::new();
+    if let Some(header) = header {
+        for part in split_quoted(header) {
+            let mut kv = part.splitn(2, '=');
+            let key = kv.next().unwrap_or("").trim().to_lowercase();
+            if key.is_empty() {
+                continue;
+            }
+            let value = kv.next().map(|v| v.trim().trim_matches('"').to_string());
+            cc.insert(key, value);
+        }
+    }
+    cc
+}
+
+fn header_map(headers: &HeaderMap) -> BTreeMap<String, String> {
+    let mut map: BTreeMap<String, String> = BTreeMap::new();
+    for (name, value) in headers.iter() {
+        if let Ok(value) = value.to_str() {
+            map.entry(name.as_str().to_string())
+                .and_modify(|v| {
+                    v.push_str(", ");
+                    v.push_str(value);
+                })
+                .or_insert_with(|| value.to_string());
+        }
+    }
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use surf::middleware::Body;
+
+    use super::*;
+    use crate::clock;
+
+    fn response(headers: &[(&str, &str)]) -> Response {
+        let mut res = http::Response::builder();
+        for (name, value) in headers {
+            res.header(*name, *value);
+        }
+        res.body(Body::empty()).unwrap()
+    }
+
+    fn request(headers: &[(&str, &str)]) -> Request {
+        let mut req = http::Request::builder();
+        req.uri("http://example.com/");
+        for (name, value) in headers {
+            req.header(*name, *value);
+        }
+        req.body(Body::empty()).unwrap()
+    }
+
+    fn shared() -> CacheOptions {
+        CacheOptions {
+            shared: true,
+            ..CacheOptions::default()
+        }
+    }
+
+    #[test]
+    fn serializes_identically_when_built_at_the_same_instant() {
+        clock::mock::freeze();
+        let req = http::Request::get("http://example.com/")
+            .body(Body::empty())
+            .unwrap();
+        let headers = [
+            ("Cache-Control", "max-age=60, public"),
+            ("ETag", "\"v1\""),
+            ("Vary", "Accept"),
+            ("Content-Type", "text/plain"),
+        ];
+        let mut reversed = headers;
+        reversed.reverse();
+
+        let policy = |headers: &[(&str, &str)]| {
+            serde_json::to_vec(&CachePolicy::new(&req, &response(headers))).unwrap()
+        };
+        assert_eq!(policy(&headers), policy(&reversed));
+
+        let before = policy(&headers);
+        clock::mock::advance(Duration::from_secs(1));
+        assert_ne!(policy(&headers), before);
+    }
+
+    #[test]
+    fn shared_caches_only_store_authorized_responses_that_allow_it() {
+        let req = request(&[("Authorization", "Bearer secret")]);
+        let res = response(&[("Cache-Control", "max-age=60")]);
+        assert!(!CachePolicy::new_options(&req, &res, shared()).is_storable());
+        assert!(CachePolicy::new(&req, &res).is_storable());
+    }
+
+    #[test]
+    fn public_lets_shared_caches_store_authorized_responses() {
+        let req = request(&[("Authorization", "Bearer secret")]);
+        for cc in &["public, max-age=60", "s-maxage=60", "must-revalidate"] {
+            let res = response(&[("Cache-Control", cc)]);
+            assert!(CachePolicy::new_options(&req, &res, shared()).is_storable());
+        }
+    }
+
+    #[test]
+    fn expires_is_counted_from_date() {
+        clock::mock::freeze();
+        let date = clock::now();
+        let res = response(&[
+            ("Date", &httpdate::fmt_http_date(date)),
+            (
+                "Expires",
+                &httpdate::fmt_http_date(date + Duration::from_secs(60)),
+            ),
+        ]);
+        let policy = CachePolicy::new(&request(&[]), &res);
+        // HTTP dates are whole seconds.
+        let ttl = policy.time_to_live(clock::now());
+        assert!(ttl > Duration::from_secs(59) && ttl <= Duration::from_secs(60));
+        assert!(!policy.is_stale(clock::now()));
+
+        clock::mock::advance(Duration::from_secs(61));
+        assert!(policy.is_stale(clock::now()));
+    }
+
+    #[test]
+    fn default_ttl_covers_responses_without_a_lifetime() {
+        clock::mock::freeze();
+        let options = CacheOptions {
+            default_ttl: Some(Duration::from_secs(30)),
+            ..CacheOptions::default()
+        };
+        let policy = CachePolicy::new_options(&request(&[]), &response(&[]), options);
+        assert!(policy.is_storable());
+        assert_eq!(policy.time_to_live(clock::now()), Duration::from_secs(30));
+
+        let own = response(&[("Cache-Control", "max-age=90")]);
+        let policy = CachePolicy::new_options(&request(&[]), &own, options);
+        assert_eq!(policy.time_to_live(clock::now()), Duration::from_secs(90));
+    }
+
+    #[test]
+    fn default_ttl_never_makes_no_store_storable() {
+        let options = CacheOptions {
+            default_ttl: Some(Duration::from_secs(30)),
+            ..CacheOptions::default()
+        };
+        let res = response(&[("Cache-Control", "no-store")]);
+        let policy = CachePolicy::new_options(&request(&[]), &res, options);
+        assert!(!policy.is_storable());
+    }
+
+    #[test]
+    fn age_counts_against_max_age() {
+        clock::mock::freeze();
+        let res = response(&[("Cache-Control", "max-age=60"), ("Age", "120")]);
+        let policy = CachePolicy::new(&request(&[]), &res);
+        assert!(policy.age(clock::now()) >= Duration::from_secs(120));
+        assert!(policy.is_stale(clock::now()));
+
+        let res = response(&[("Cache-Control", "max-age=60"), ("Age", "30")]);
+        let policy = CachePolicy::new(&request(&[]), &res);
+        assert!(!policy.is_stale(clock::now()));
+        assert_eq!(policy.time_to_live(clock::now()), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn future_dates_count_as_no_age_at_all() {
+        clock::mock::freeze();
+        let date = clock::now() + Duration::from_secs(3600);
+        let res = response(&[
+            ("Date", &httpdate::fmt_http_date(date)),
+            ("Cache-Control", "max-age=60"),
+        ]);
+        let policy = CachePolicy::new(&request(&[]), &res);
+        assert_eq!(policy.age(clock::now()), Duration::from_secs(0));
+        assert_eq!(policy.time_to_live(clock::now()), Duration::from_secs(60));
+
+        clock::mock::advance(Duration::from_secs(61));
+        assert!(policy.is_stale(clock::now()));
+    }
+
+    #[test]
+    fn missing_dates_count_from_when_the_response_arrived() {
+        clock::mock::freeze();
+        let res = response(&[("Cache-Control", "max-age=60")]);
+        let policy = CachePolicy::new(&request(&[]), &res);
+        assert_eq!(policy.age(clock::now()), Duration::from_secs(0));
+
+        clock::mock::advance(Duration::from_secs(10));
+        assert_eq!(policy.age(clock::now()), Duration::from_secs(10));
+        assert_eq!(policy.time_to_live(clock::now()), Duration::from_secs(50));
+    }
+
+    #[test]
+    fn s_maxage_only_applies_to_shared_caches() {
+        clock::mock::freeze();
+        let res = response(&[("Cache-Control", "max-age=10, s-maxage=100")]);
+        let private = CachePolicy::new(&request(&[]), &res);
+        let shared = CachePolicy::new_options(&request(&[]), &res, shared());
+
+        clock::mock::advance(Duration::from_secs(50));
+        assert!(private.is_stale(clock::now()));
+        assert!(!shared.is_stale(clock::now()));
+        assert_eq!(shared.time_to_live(clock::now()), Duration::from_secs(50));
+    }
+
+    #[test]
+    fn proxy_revalidate_only_binds_shared_caches() {
+        let res = response(&[("Cache-Control", "public, max-age=60, proxy-revalidate")]);
+        let private = CachePolicy::new(&request(&[]), &res);
+        assert!(!private.must_revalidate());
+        let shared = CachePolicy::new_options(&request(&[]), &res, shared());
+        assert!(shared.must_revalidate());
+    }
+
+    #[test]
+    fn surrogate_control_overrides_no_store_for_surrogates() {
+        let res = response(&[
+            ("Cache-Control", "no-store"),
+            ("Surrogate-Control", "max-age=60"),
+        ]);
+        let surrogate = CacheOptions {
+            surrogate: true,
+            ..CacheOptions::default()
+        };
+        let policy = CachePolicy::new_options(&request(&[]), &res, surrogate);
+        assert!(policy.is_storable());
+        assert_eq!(policy.max_age(), Duration::from_secs(60));
+
+        let policy = CachePolicy::new(&request(&[]), &res);
+        assert!(!policy.is_storable());
+    }
+}